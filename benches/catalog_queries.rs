@@ -0,0 +1,107 @@
+//! 大量のテーブルを持つ SQLite のスキーマを生成し、統計情報 (statistics: true) を含めて読み込む際の
+//! 変更前の読み込み方 (テーブルごとに統計情報を問い合わせる) と現在の read_db のクエリ回数を比較する
+//!
+//! cargo bench --features query-count --bench catalog_queries -- [テーブル数]
+extern crate mylib;
+extern crate sqlite3;
+
+use mylib::config::db_config::DbConfig;
+use mylib::db;
+use mylib::erdh::table_filter::TableMatcher;
+use std::env;
+use std::time::Instant;
+
+fn main() {
+    let table_count: usize = env::args().skip(1)
+        .find(|a| !a.starts_with('-'))
+        .and_then(|a| a.parse().ok())
+        .unwrap_or(3000);
+
+    let path = env::temp_dir().join(format!("erdh_bench_{}.sqlite3", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    generate_schema(&path, table_count);
+
+    let conn = sqlite3::open(&path).unwrap();
+    let start = Instant::now();
+    let before_queries = read_before(&conn);
+    let before_elapsed = start.elapsed();
+
+    let config = DbConfig::from_yaml(&format!("dbtype: sqlite\ndbname: {}\nstatistics: true\n", path)).unwrap();
+    db::reset_query_count();
+    let start = Instant::now();
+    let cons = db::sqlite::read_db(&config, &TableMatcher::default()).unwrap();
+    let after_elapsed = start.elapsed();
+    let after_queries = db::query_count();
+
+    println!("tables: {}", cons.tables.len());
+    println!("before: {:>6} queries, {:?}", before_queries, before_elapsed);
+    println!("after:  {:>6} queries, {:?}", after_queries, after_elapsed);
+
+    std::fs::remove_file(&path).ok();
+}
+
+/// 外部キーとインデックスを持つテーブルを table_count 個作る
+fn generate_schema(path: &str, table_count: usize) {
+    std::fs::remove_file(path).ok();
+    let conn = sqlite3::open(path).unwrap();
+    let mut ddl = String::from("BEGIN;\n");
+    for i in 0..table_count {
+        let reference = if i == 0 {
+            "".to_string()
+        } else {
+            format!(", parent_id INTEGER, FOREIGN KEY (parent_id) REFERENCES t{} (id)", i - 1)
+        };
+        ddl.push_str(&format!(
+            "CREATE TABLE t{} (id INTEGER PRIMARY KEY AUTOINCREMENT, code VARCHAR(32) NOT NULL, name TEXT, created_at DATETIME{});\n",
+            i, reference));
+        ddl.push_str(&format!("CREATE INDEX t{}_code ON t{} (code);\n", i, i));
+    }
+    ddl.push_str("COMMIT;\n");
+    conn.execute(&ddl).unwrap();
+}
+
+/// 変更前の sqlite::read_db が発行していたクエリをそのまま発行する
+/// (sqlite_master からテーブルを1回、テーブルごとに read_statistics の3回、最後にトリガーを1回)
+fn read_before(conn: &sqlite3::Connection) -> usize {
+    let mut queries = 0;
+    let mut table_names = vec![];
+    let mut cursor = conn.prepare(r#"SELECT tbl_name, sql FROM sqlite_master WHERE type = "table""#).unwrap().cursor();
+    queries += 1;
+    while let Some(row) = cursor.next().unwrap() {
+        table_names.push(row[0].as_string().unwrap().to_string());
+    }
+
+    for table_name in &table_names {
+        queries += read_statistics_before(conn, table_name);
+    }
+
+    let mut cursor = conn.prepare(r#"SELECT name, tbl_name, sql FROM sqlite_master WHERE type = "trigger""#).unwrap().cursor();
+    queries += 1;
+    while let Some(_) = cursor.next().unwrap() {}
+
+    queries
+}
+
+/// 変更前の sqlite::read_statistics と同じクエリ
+fn read_statistics_before(conn: &sqlite3::Connection, table_name: &str) -> usize {
+    let count_query = format!(r#"SELECT COUNT(*) FROM "{}""#, table_name.replace("\"", "\"\""));
+    let data_query = "SELECT SUM(pgsize) FROM dbstat WHERE name = ?";
+    let index_query = r#"
+        SELECT SUM(s.pgsize)
+          FROM dbstat s
+               JOIN sqlite_master m ON m.name = s.name
+         WHERE m.type = 'index'
+           AND m.tbl_name = ?"#;
+    let queries: [(&str, Option<&str>); 3] = [(&count_query, None), (data_query, Some(table_name)), (index_query, Some(table_name))];
+    for (query, param) in queries.iter() {
+        // dbstat が無効な SQLite では失敗するが、変更前と同じく問い合わせとして数える
+        if let Ok(statement) = conn.prepare(*query) {
+            let mut cursor = statement.cursor();
+            if let Some(param) = param {
+                cursor.bind(&[sqlite3::Value::String(param.to_string())]).ok();
+            }
+            cursor.next().ok();
+        }
+    }
+    queries.len()
+}
@@ -0,0 +1,130 @@
+//! 設定ファイルと、そこから参照するファイルをまとめて検証する
+//! データベースには接続しない
+use super::config::{Config, SourceType, check_file};
+use super::config::db_config::DbConfig;
+use super::config::extra_config::ExtraConfig;
+use super::erdh::table_filter::TableMatcher;
+use super::schema::{self, SchemaKind};
+use std::path::Path;
+
+/// 見つかった問題をすべて返す。問題が無ければ空
+pub fn check_config(path: &str) -> Vec<String> {
+    if let Err(e) = schema::validate_file(SchemaKind::Config, path) {
+        return vec![e.to_string()];
+    }
+    let config = match Config::from_file(path) {
+        Ok(config) => config,
+        Err(e) => return vec![format!("{}: {}", path, e)],
+    };
+
+    let mut problems = vec![];
+    for (key, referenced) in config.referenced_files() {
+        if let Err(e) = check_file(&key, &referenced) {
+            problems.push(format!("{}: {}", path, e));
+        }
+    }
+    if problems.len() > 0 {
+        return problems;
+    }
+
+    for source in config.get_sources().unwrap_or(vec![]) {
+        let kind = match source.source {
+            SourceType::Intermediate => SchemaKind::Intermediate,
+            _ => SchemaKind::DbConfig,
+        };
+        if let Err(e) = schema::validate_file(kind, &source.source_from) {
+            problems.push(e.to_string());
+            continue;
+        }
+        if kind == SchemaKind::DbConfig {
+            problems.extend(check_db_config(&source.source_from));
+        }
+    }
+    match config.get_ex_info_files() {
+        Ok(files) => for ex_info in &files {
+            match schema::validate_file(SchemaKind::ExInfo, ex_info) {
+                Ok(_) => problems.extend(check_ex_info(ex_info)),
+                Err(e) => problems.push(e.to_string()),
+            }
+        },
+        Err(e) => problems.push(format!("{}: {}", path, e)),
+    }
+
+    if let Err(e) = TableMatcher::new(config.tables.as_ref()) {
+        problems.push(format!("{}: tables: {}", path, e));
+    }
+    for (i, pattern) in config.table_patterns.iter().flatten().enumerate() {
+        if let Err(e) = pattern.regex() {
+            problems.push(format!("{}: table_patterns[{}].pattern: {}", path, i, e));
+        }
+    }
+    let mut written_files = vec![];
+    if let Some(save_to) = config.intermediate.as_ref().and_then(|im| im.save_to.as_ref()) {
+        written_files.push(("intermediate.save_to".to_string(), save_to.clone()));
+    }
+    for (i, output) in config.outputs.iter().flatten().enumerate() {
+        written_files.push((format!("outputs[{}].path", i), output.path.clone()));
+    }
+    for (key, written) in written_files {
+        // グループごとに分かれるディレクトリは出力時に決まる
+        let dir = Path::new(&written).parent()
+            .filter(|d| d.as_os_str().len() > 0 && !d.to_string_lossy().contains("{group}"));
+        if let Some(dir) = dir {
+            if !dir.is_dir() {
+                problems.push(format!("{}: {}: directory {} does not exist", path, key, dir.display()));
+            }
+        }
+    }
+
+    problems
+}
+
+fn check_db_config(path: &str) -> Vec<String> {
+    let db_config = match DbConfig::from_file(path) {
+        Ok(db_config) => db_config,
+        Err(e) => return vec![format!("{}: {}", path, e)],
+    };
+    db_config.referenced_files().into_iter()
+        .filter_map(|(key, referenced)| check_file(&key, &referenced).err())
+        .map(|e| format!("{}: {}", path, e))
+        .collect()
+}
+
+fn check_ex_info(path: &str) -> Vec<String> {
+    let ex_info = match ExtraConfig::from_file(path) {
+        Ok(ex_info) => ex_info,
+        Err(e) => return vec![format!("{}: {}", path, e)],
+    };
+    ex_info.rules.iter().enumerate()
+        .filter_map(|(i, rule)| rule.matchers().err().map(|e| format!("{}: rules[{}]: {}", path, i, e)))
+        .collect()
+}
+
+#[test]
+fn check_config_success() {
+    use std::fs;
+    let dir = std::env::temp_dir().join(format!("erdh_check_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("db.yaml"), "dbtype: sqlite\ndbname: test.sqlite3\n").unwrap();
+    fs::write(dir.join("config.yaml"), "source: sqlite\nsource_from: db.yaml\nex_info: ex_info.yaml\n").unwrap();
+    let config_path = dir.join("config.yaml").to_string_lossy().to_string();
+
+    // ex_info も SQLite のファイルも無い
+    let problems = check_config(&config_path);
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert_eq!(problems[0].contains("ex_info: cannot open"), true, "{:?}", problems);
+
+    fs::write(dir.join("ex_info.yaml"), "tables:\n- table: members\n  is_mastr: true\n").unwrap();
+    let problems = check_config(&config_path);
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert_eq!(problems[0].contains("db.yaml: dbname: cannot open"), true, "{:?}", problems);
+    assert_eq!(problems[1].contains("ex_info.yaml:3:"), true, "{:?}", problems);
+    assert_eq!(problems[1].contains("did you mean `is_master`?"), true, "{:?}", problems);
+
+    fs::write(dir.join("config.yaml"), "source: sqlite\nsource_from: db.yaml\ntable_patterns:\n- pattern: ^log_\\d{6}$\n").unwrap();
+    let problems = check_config(&config_path);
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert_eq!(problems[1].contains("table_patterns[0].pattern: ^log_\\d{6}$ has no capture group"), true, "{:?}", problems);
+
+    fs::remove_dir_all(&dir).ok();
+}
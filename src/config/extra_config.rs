@@ -1,13 +1,27 @@
 use serde_derive::{Serialize, Deserialize};
+use schemars::JsonSchema;
 extern crate serde_yaml;
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::Read;
 extern crate failure;
 use super::super::erdh::erdh_data;
+use super::super::file_format::{self, FileFormat};
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::Write;
+extern crate serde_json;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ExtraConfig {
+    /// 名前のパターンでまとめて指定する規則 (tables の指定の方が優先される)
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
     pub tables: Vec<Table>,
+    /// グループの表示設定
+    #[serde(default)]
+    pub groups: Vec<erdh_data::Group>,
 }
 
 impl ExtraConfig {
@@ -19,17 +33,326 @@ impl ExtraConfig {
     }
 
     pub fn from_yaml(yaml: &str) -> Result<ExtraConfig, failure::Error> {
-        let result: ExtraConfig = serde_yaml::from_str(&yaml)?;
+        Self::from_contents(yaml, FileFormat::Yaml)
+    }
+
+    /// YAML, JSON, TOML のいずれかのファイルから読み込む
+    pub fn from_file(path: &str) -> Result<ExtraConfig, failure::Error> {
+        let (contents, format) = file_format::read_file(path)?;
+        Self::from_contents(&contents, format)
+    }
+
+    pub fn from_contents(contents: &str, format: FileFormat) -> Result<ExtraConfig, failure::Error> {
+        format.parse(contents)
+    }
+}
+
+/// 複数の ex_info を順に重ねたもの
+/// - rules: 後のファイルの規則ほど前に並ぶ (priority が同じなら後のファイルが優先される)
+/// - tables: 同じテーブルの指定は1つにまとめ、後のファイルで指定した項目で上書きする
+///   - relations: 参照先テーブルが同じものは置き換え、それ以外は追加する
+///   - remove_relations: 追加する
+///   - columns: 同じ列の指定は1つにまとめ、項目ごとに上書きする
+/// - groups: 同じグループの指定は1つにまとめ、項目ごとに上書きする
+pub struct LayeredExtraConfig {
+    pub ex_info: ExtraConfig,
+    /// rules と同じ順序で、それぞれの規則を書いたファイル
+    rule_origins: Vec<String>,
+    /// "members.group" や "members.columns.id.note" のような項目と、その値を決めたファイル
+    origins: HashMap<String, String>,
+}
+
+impl LayeredExtraConfig {
+    pub fn new() -> LayeredExtraConfig {
+        LayeredExtraConfig {
+            ex_info: ExtraConfig { rules: vec![], tables: vec![], groups: vec![] },
+            rule_origins: vec![],
+            origins: HashMap::new(),
+        }
+    }
+
+    /// ファイルを順に読み込んで重ねる
+    pub fn from_files(paths: &Vec<String>) -> Result<LayeredExtraConfig, failure::Error> {
+        let mut result = Self::new();
+        for path in paths {
+            let layer = ExtraConfig::from_file(path).map_err(|e| failure::format_err!("{}: {}", path, e))?;
+            result.push(path, layer);
+        }
         Ok(result)
     }
+
+    /// origin から読み込んだ layer を上に重ねる
+    pub fn push(&mut self, origin: &str, layer: ExtraConfig) {
+        let count = layer.rules.len();
+        let mut rules = layer.rules;
+        rules.extend(self.ex_info.rules.drain(..));
+        self.ex_info.rules = rules;
+        let mut rule_origins = vec![origin.to_string(); count];
+        rule_origins.extend(self.rule_origins.drain(..));
+        self.rule_origins = rule_origins;
+
+        for table in layer.tables {
+            let index = match self.ex_info.tables.iter().position(|t| t.table == table.table) {
+                Some(index) => index,
+                None => {
+                    self.ex_info.tables.push(Table::new(&table.table));
+                    self.ex_info.tables.len() - 1
+                }
+            };
+            let target = &mut self.ex_info.tables[index];
+            let origins = &mut self.origins;
+            let key = target.table.clone();
+            merge_field(&mut target.is_master, table.is_master, format!("{}.is_master", key), origin, origins);
+            merge_field(&mut target.group, table.group, format!("{}.group", key), origin, origins);
+            merge_field(&mut target.hide, table.hide, format!("{}.hide", key), origin, origins);
+            merge_field(&mut target.display_name, table.display_name, format!("{}.display_name", key), origin, origins);
+            merge_field(&mut target.column_order, table.column_order, format!("{}.column_order", key), origin, origins);
+            merge_field(&mut target.note, table.note, format!("{}.note", key), origin, origins);
+            // 外したリレーションは前のファイルの relations からも除き、書いたリレーションは前のファイルの remove_relations から除く
+            for name in table.remove_relations.into_iter().flatten() {
+                if let Some(relations) = target.relations.as_mut() {
+                    relations.retain(|r| {
+                        let removed = name == "*" || r.referenced_table_name == name;
+                        if removed {
+                            origins.remove(&format!("{}.relations.{}", key, r.referenced_table_name));
+                        }
+                        !removed
+                    });
+                }
+                let remove_relations = target.remove_relations.get_or_insert(vec![]);
+                origins.insert(format!("{}.remove_relations.{}", key, name), origin.to_string());
+                if !remove_relations.contains(&name) {
+                    remove_relations.push(name);
+                }
+            }
+            for relation in table.relations.into_iter().flatten() {
+                if let Some(remove_relations) = target.remove_relations.as_mut() {
+                    if let Some(i) = remove_relations.iter().position(|r| r == &relation.referenced_table_name) {
+                        remove_relations.remove(i);
+                        origins.remove(&format!("{}.remove_relations.{}", key, relation.referenced_table_name));
+                    }
+                }
+                let relations = target.relations.get_or_insert(vec![]);
+                origins.insert(format!("{}.relations.{}", key, relation.referenced_table_name), origin.to_string());
+                match relations.iter().position(|r| r.referenced_table_name == relation.referenced_table_name) {
+                    Some(i) => relations[i] = relation,
+                    None => relations.push(relation),
+                }
+            }
+            if target.relations.as_ref().map_or(false, |r| r.len() == 0) {
+                target.relations = None;
+            }
+            if target.remove_relations.as_ref().map_or(false, |r| r.len() == 0) {
+                target.remove_relations = None;
+            }
+            for column in table.columns.into_iter().flatten() {
+                let columns = target.columns.get_or_insert(vec![]);
+                let index = match columns.iter().position(|c| c.name == column.name) {
+                    Some(index) => index,
+                    None => {
+                        columns.push(Column { name: column.name.clone(), hide: None, display_name: None, note: None });
+                        columns.len() - 1
+                    }
+                };
+                let target_column = &mut columns[index];
+                let key = format!("{}.columns.{}", key, column.name);
+                merge_field(&mut target_column.hide, column.hide, format!("{}.hide", key), origin, origins);
+                merge_field(&mut target_column.display_name, column.display_name, format!("{}.display_name", key), origin, origins);
+                merge_field(&mut target_column.note, column.note, format!("{}.note", key), origin, origins);
+            }
+        }
+
+        for group in layer.groups {
+            let index = match self.ex_info.groups.iter().position(|g| g.name == group.name) {
+                Some(index) => index,
+                None => {
+                    self.ex_info.groups.push(erdh_data::Group {
+                        name: group.name.clone(), display_name: None, color: None, style: None, description: None,
+                    });
+                    self.ex_info.groups.len() - 1
+                }
+            };
+            let target = &mut self.ex_info.groups[index];
+            let origins = &mut self.origins;
+            let key = format!("groups.{}", group.name);
+            merge_field(&mut target.display_name, group.display_name, format!("{}.display_name", key), origin, origins);
+            merge_field(&mut target.color, group.color, format!("{}.color", key), origin, origins);
+            merge_field(&mut target.style, group.style, format!("{}.style", key), origin, origins);
+            merge_field(&mut target.description, group.description, format!("{}.description", key), origin, origins);
+        }
+    }
+
+    /// 重ねた結果を、値ごとにそれを決めたファイルをコメントで付けた YAML で書き出す
+    pub fn write_with_origins<W: Write>(&self, writer: &mut W) -> Result<(), failure::Error> {
+        writer.write(b"rules:")?;
+        if self.ex_info.rules.len() == 0 {
+            writer.write(b" []")?;
+        }
+        writer.write(b"\n")?;
+        for (rule, origin) in self.ex_info.rules.iter().zip(&self.rule_origins) {
+            writer.write(format!("  - {}  # {}\n", to_flow(rule)?, origin).as_bytes())?;
+        }
+
+        writer.write(b"tables:")?;
+        if self.ex_info.tables.len() == 0 {
+            writer.write(b" []")?;
+        }
+        writer.write(b"\n")?;
+        for table in &self.ex_info.tables {
+            let key = &table.table;
+            writer.write(format!("  - table: {}\n", to_flow(&table.table)?).as_bytes())?;
+            self.write_field(writer, 4, "is_master", &table.is_master, &format!("{}.is_master", key))?;
+            self.write_field(writer, 4, "group", &table.group, &format!("{}.group", key))?;
+            self.write_field(writer, 4, "hide", &table.hide, &format!("{}.hide", key))?;
+            self.write_field(writer, 4, "display_name", &table.display_name, &format!("{}.display_name", key))?;
+            self.write_field(writer, 4, "column_order", &table.column_order, &format!("{}.column_order", key))?;
+            self.write_field(writer, 4, "note", &table.note, &format!("{}.note", key))?;
+            if let Some(relations) = &table.relations {
+                writer.write(b"    relations:\n")?;
+                for relation in relations {
+                    let origin = &self.origins[&format!("{}.relations.{}", key, relation.referenced_table_name)];
+                    writer.write(format!("      - {}  # {}\n", to_flow(relation)?, origin).as_bytes())?;
+                }
+            }
+            if let Some(remove_relations) = &table.remove_relations {
+                writer.write(b"    remove_relations:\n")?;
+                for name in remove_relations {
+                    let origin = &self.origins[&format!("{}.remove_relations.{}", key, name)];
+                    writer.write(format!("      - {}  # {}\n", to_flow(name)?, origin).as_bytes())?;
+                }
+            }
+            if let Some(columns) = &table.columns {
+                writer.write(b"    columns:\n")?;
+                for column in columns {
+                    let key = format!("{}.columns.{}", key, column.name);
+                    writer.write(format!("      - name: {}\n", to_flow(&column.name)?).as_bytes())?;
+                    self.write_field(writer, 8, "hide", &column.hide, &format!("{}.hide", key))?;
+                    self.write_field(writer, 8, "display_name", &column.display_name, &format!("{}.display_name", key))?;
+                    self.write_field(writer, 8, "note", &column.note, &format!("{}.note", key))?;
+                }
+            }
+        }
+
+        if self.ex_info.groups.len() > 0 {
+            writer.write(b"groups:\n")?;
+        }
+        for group in &self.ex_info.groups {
+            let key = format!("groups.{}", group.name);
+            writer.write(format!("  - name: {}\n", to_flow(&group.name)?).as_bytes())?;
+            self.write_field(writer, 4, "display_name", &group.display_name, &format!("{}.display_name", key))?;
+            self.write_field(writer, 4, "color", &group.color, &format!("{}.color", key))?;
+            self.write_field(writer, 4, "style", &group.style, &format!("{}.style", key))?;
+            self.write_field(writer, 4, "description", &group.description, &format!("{}.description", key))?;
+        }
+        Ok(())
+    }
+
+    fn write_field<W: Write, T: serde::Serialize>(&self, writer: &mut W, indent: usize, name: &str, value: &Option<T>, key: &str) -> Result<(), failure::Error> {
+        if let Some(value) = value {
+            writer.write(format!("{:indent$}{}: {}  # {}\n", "", name, to_flow(value)?, self.origins[key], indent = indent).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// 後から指定した値があれば上書きし、その値を決めたファイルを記録する
+fn merge_field<T>(target: &mut Option<T>, value: Option<T>, key: String, origin: &str, origins: &mut HashMap<String, String>) {
+    if value.is_some() {
+        *target = value;
+        origins.insert(key, origin.to_string());
+    }
+}
+
+/// 1行で書ける形 (JSON は YAML のフロー形式としても読める) にする。未指定の項目は省く
+fn to_flow<T: serde::Serialize>(value: &T) -> Result<String, failure::Error> {
+    let mut value = serde_json::to_value(value)?;
+    if let serde_json::Value::Object(map) = &mut value {
+        map.retain(|_, v| !v.is_null());
+    }
+    Ok(serde_json::to_string(&value)?)
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Table {
     pub table: String,
     pub is_master: Option<bool>,
     pub group: Option<String>,
     pub relations: Option<Vec<erdh_data::ExRelation>>,
+    /// 図に出力しない
+    pub hide: Option<bool>,
+    /// 削除するリレーションの参照先テーブル ("*" ならすべて)
+    pub remove_relations: Option<Vec<String>>,
+    pub display_name: Option<String>,
+    /// 先頭に並べる列 (指定しなかった列は元の順序でその後に並ぶ)
+    pub column_order: Option<Vec<String>>,
+    pub note: Option<String>,
+    pub columns: Option<Vec<Column>>,
+}
+
+impl Table {
+    fn new(table: &str) -> Table {
+        Table {
+            table: table.to_string(),
+            is_master: None,
+            group: None,
+            relations: None,
+            hide: None,
+            remove_relations: None,
+            display_name: None,
+            column_order: None,
+            note: None,
+            columns: None,
+        }
+    }
+}
+
+/// 列ごとの指定
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Column {
+    pub name: String,
+    /// 図に出力しない
+    pub hide: Option<bool>,
+    pub display_name: Option<String>,
+    pub note: Option<String>,
+}
+
+/// 名前が一致するテーブルにまとめて適用する規則
+/// pattern, prefix, suffix のうち指定したものすべてに一致するテーブルが対象
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// 正規表現 ("^mst_.*" など)
+    pub pattern: Option<String>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+    /// 大きいほど優先される (省略時は 0)。同じ場合は先に書いた規則が優先される
+    pub priority: Option<i32>,
+    pub is_master: Option<bool>,
+    pub group: Option<String>,
+    /// 図に出力しない
+    pub hide: Option<bool>,
+}
+
+impl Rule {
+    /// 名前の条件を正規表現の一覧にする
+    pub fn matchers(&self) -> Result<Vec<Regex>, failure::Error> {
+        let mut result = vec![];
+        if let Some(pattern) = &self.pattern {
+            result.push(Regex::new(pattern)?);
+        }
+        if let Some(prefix) = &self.prefix {
+            result.push(Regex::new(&format!("^{}", regex::escape(prefix)))?);
+        }
+        if let Some(suffix) = &self.suffix {
+            result.push(Regex::new(&format!("{}$", regex::escape(suffix)))?);
+        }
+        if result.len() == 0 {
+            return Err(failure::format_err!("rule needs at least one of pattern, prefix or suffix"));
+        }
+        Ok(result)
+    }
 }
 
 #[test]
@@ -66,3 +389,97 @@ tables:
     let c = ExtraConfig::from_yaml(&yaml);
     assert_eq!(c.is_ok(), true);
 }
+
+#[test]
+fn layered_extra_config_success() {
+    let org = r#"
+rules:
+  - prefix: mst_
+    group: MASTER
+tables:
+  - table: members
+    group: MEMBER
+    note: organisation note
+    relations:
+      - {referenced_table_name: teams, columns: [{from: team_id, to: id}], this_conn: many, that_conn: one}
+    columns:
+      - {name: email, note: login id}
+"#;
+    let service = r#"
+rules:
+  - prefix: mst_
+    group: SERVICE_MASTER
+tables:
+  - table: members
+    note: service note
+    relations:
+      - {referenced_table_name: teams, columns: [{from: team_id, to: id}], this_conn: many, that_conn: zero-or-one}
+    remove_relations: [logs]
+    columns:
+      - {name: email, display_name: mail}
+  - table: orders
+    hide: true
+"#;
+    let mut layered = LayeredExtraConfig::new();
+    layered.push("org.yaml", ExtraConfig::from_yaml(org).unwrap());
+    layered.push("service.yaml", ExtraConfig::from_yaml(service).unwrap());
+
+    let ex = &layered.ex_info;
+    assert_eq!(ex.rules[0].group, Some("SERVICE_MASTER".to_string()));
+    assert_eq!(ex.rules[1].group, Some("MASTER".to_string()));
+    assert_eq!(ex.tables.len(), 2);
+    assert_eq!(ex.tables[0].group, Some("MEMBER".to_string()));
+    assert_eq!(ex.tables[0].note, Some("service note".to_string()));
+    let relations = ex.tables[0].relations.as_ref().unwrap();
+    assert_eq!(relations.len(), 1);
+    assert_eq!(relations[0].that_conn, erdh_data::Connection::ZeroOrOne);
+    let column = &ex.tables[0].columns.as_ref().unwrap()[0];
+    assert_eq!((column.note.as_ref().unwrap().as_str(), column.display_name.as_ref().unwrap().as_str()), ("login id", "mail"));
+
+    let mut out = vec![];
+    layered.write_with_origins(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.contains("  - {\"group\":\"SERVICE_MASTER\",\"prefix\":\"mst_\"}  # service.yaml\n"), true, "{}", out);
+    assert_eq!(out.contains("    group: \"MEMBER\"  # org.yaml\n"), true, "{}", out);
+    assert_eq!(out.contains("    note: \"service note\"  # service.yaml\n"), true, "{}", out);
+    assert_eq!(out.contains("        note: \"login id\"  # org.yaml\n"), true, "{}", out);
+    // 書き出した結果はそのまま ex_info として読める
+    assert_eq!(&ExtraConfig::from_yaml(&out).unwrap(), ex);
+}
+
+#[test]
+fn layered_extra_config_remove_relations_success() {
+    let org = r#"
+tables:
+  - table: members
+    relations:
+      - {referenced_table_name: teams, columns: [{from: team_id, to: id}], this_conn: many, that_conn: one}
+    remove_relations: [logs]
+"#;
+    let service = r#"
+tables:
+  - table: members
+    relations:
+      - {referenced_table_name: logs, columns: [{from: id, to: member_id}], this_conn: one, that_conn: many}
+    remove_relations: [teams]
+"#;
+    let mut layered = LayeredExtraConfig::new();
+    layered.push("org.yaml", ExtraConfig::from_yaml(org).unwrap());
+    layered.push("service.yaml", ExtraConfig::from_yaml(service).unwrap());
+    let members = &layered.ex_info.tables[0];
+    let relations = members.relations.as_ref().unwrap();
+    assert_eq!(relations.len(), 1);
+    assert_eq!(relations[0].referenced_table_name, "logs");
+    assert_eq!(members.remove_relations, Some(vec!["teams".to_string()]));
+
+    layered.push("local.yaml", ExtraConfig::from_yaml("tables:\n  - {table: members, remove_relations: [\"*\"]}\n").unwrap());
+    let members = &layered.ex_info.tables[0];
+    assert_eq!(members.relations, None);
+    assert_eq!(members.remove_relations, Some(vec!["teams".to_string(), "*".to_string()]));
+
+    let mut out = vec![];
+    layered.write_with_origins(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.contains("      - \"*\"  # local.yaml\n"), true, "{}", out);
+    assert_eq!(&ExtraConfig::from_yaml(&out).unwrap(), &layered.ex_info);
+}
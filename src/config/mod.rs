@@ -1,20 +1,38 @@
 use serde_derive::{Serialize, Deserialize};
+use schemars::JsonSchema;
 extern crate serde_yaml;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 extern crate failure;
+use super::file_format::{self, FileFormat};
+use regex::Regex;
 
 pub mod db_config;
 pub mod extra_config;
+pub mod credentials;
 
 // #[serde(rename_all = "snake_case")]
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub source: SourceType,
-    pub source_from: String,
+    /// 読み込み元が1つの場合に指定する (複数の場合は sources)
+    pub source: Option<SourceType>,
+    pub source_from: Option<String>,
+    /// 複数の読み込み元を1つにまとめる場合に指定する
+    pub sources: Option<Vec<Source>>,
+    /// 出力するグループ ("Sales" を指定すると "Sales/Orders" なども含む)
     pub group: Option<Vec<String>>,
     pub intermediate: Option<Intermediate>,
-    pub ex_info: Option<String>,
+    /// ex_info のファイル。複数のファイルやディレクトリを指定すると順に重ねる (後のものが優先される)
+    pub ex_info: Option<PathList>,
+    /// true なら ex_info の警告 (存在しないテーブルや列など) をエラーとして扱う
+    pub ex_info_strict: Option<bool>,
+    pub plantuml: Option<PlantUmlOption>,
+    pub table_patterns: Option<Vec<TablePattern>>,
+    pub tables: Option<TableFilter>,
+    /// 1回の読み込みから出力する図の一覧
+    pub outputs: Option<Vec<Output>>,
 }
 
 impl Config {
@@ -26,19 +44,326 @@ impl Config {
     }
 
     pub fn from_yaml(yaml: &str) -> Result<Config, failure::Error> {
-        let result: Config = serde_yaml::from_str(&yaml)?;
+        Self::from_contents(yaml, FileFormat::Yaml)
+    }
+
+    /// YAML, JSON, TOML のいずれかのファイルから読み込む
+    /// 相対パスは設定ファイルのあるディレクトリからのパスとする
+    pub fn from_file(path: &str) -> Result<Config, failure::Error> {
+        let (contents, format) = file_format::read_file(path)?;
+        let mut result = Self::from_contents(&contents, format)?;
+        result.resolve_paths(&base_dir(path));
+        Ok(result)
+    }
+
+    pub fn from_contents(contents: &str, format: FileFormat) -> Result<Config, failure::Error> {
+        format.parse(contents)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        if let Some(path) = self.source_from.as_mut() {
+            *path = resolve_path(base, path);
+        }
+        for source in self.sources.iter_mut().flatten() {
+            source.source_from = resolve_path(base, &source.source_from);
+        }
+        for path in self.ex_info.iter_mut().flat_map(|p| p.iter_mut()) {
+            *path = resolve_path(base, path);
+        }
+        if let Some(path) = self.intermediate.as_mut().and_then(|im| im.save_to.as_mut()) {
+            *path = resolve_path(base, path);
+        }
+        for output in self.outputs.iter_mut().flatten() {
+            output.path = resolve_path(base, &output.path);
+        }
+    }
+
+    /// 読み込むファイルとそれを指定している設定項目の組
+    pub fn referenced_files(&self) -> Vec<(String, String)> {
+        let mut result = vec![];
+        if let Some(path) = &self.source_from {
+            result.push(("source_from".to_string(), path.clone()));
+        }
+        for (i, source) in self.sources.iter().flatten().enumerate() {
+            result.push((format!("sources[{}].source_from", i), source.source_from.clone()));
+        }
+        for (i, path) in self.ex_info.iter().flat_map(|p| p.iter()).enumerate() {
+            let key = match self.ex_info {
+                Some(PathList::Many(_)) => format!("ex_info[{}]", i),
+                _ => "ex_info".to_string(),
+            };
+            result.push((key, path.clone()));
+        }
+        result
+    }
+
+    /// outputs と、コマンドラインで指定した出力先 (group, plantuml を使う) を出力の一覧にする
+    pub fn get_outputs(&self, output_path: Option<&str>) -> Result<Vec<Output>, failure::Error> {
+        let mut result = self.outputs.clone().unwrap_or(vec![]);
+        if let Some(path) = output_path {
+            result.push(Output {
+                path: path.to_string(),
+                format: None,
+                group: self.group.clone(),
+                detail: None,
+                plantuml: self.plantuml.clone(),
+            });
+        }
+        if result.len() == 0 {
+            return Err(failure::format_err!("no output: specify -o or outputs in the config"));
+        }
         Ok(result)
     }
+
+    /// ex_info に指定したファイルを重ねる順に並べる
+    /// ディレクトリはその中の yaml, yml, json, toml ファイルを名前順に並べる
+    pub fn get_ex_info_files(&self) -> Result<Vec<String>, failure::Error> {
+        let mut result = vec![];
+        for path in self.ex_info.iter().flat_map(|p| p.iter()) {
+            if !Path::new(path).is_dir() {
+                result.push(path.clone());
+                continue;
+            }
+            let mut files = vec![];
+            for entry in std::fs::read_dir(path).map_err(|e| failure::format_err!("ex_info: cannot read {}: {}", path, e))? {
+                let entry_path = entry?.path();
+                if entry_path.is_file() && FileFormat::from_path(&entry_path.to_string_lossy()).is_some() {
+                    files.push(entry_path.to_string_lossy().to_string());
+                }
+            }
+            files.sort();
+            result.extend(files);
+        }
+        Ok(result)
+    }
+
+    /// source, source_from と sources を読み込み元の一覧にする
+    pub fn get_sources(&self) -> Result<Vec<Source>, failure::Error> {
+        match (&self.source, &self.source_from, &self.sources) {
+            (None, None, Some(sources)) if sources.len() > 0 => Ok(sources.clone()),
+            (Some(source), Some(source_from), None) => Ok(vec![Source {
+                name: None,
+                source: source.clone(),
+                source_from: source_from.clone(),
+                group: None,
+            }]),
+            _ => Err(failure::format_err!("specify either source and source_from, or a non-empty sources list")),
+        }
+    }
+}
+
+/// 設定ファイルのあるディレクトリ
+pub fn base_dir(config_path: &str) -> PathBuf {
+    Path::new(config_path).parent().map(|p| p.to_path_buf()).unwrap_or(PathBuf::new())
+}
+
+/// 相対パスを base からのパスにする
+pub fn resolve_path(base: &Path, path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    base.join(path).to_string_lossy().to_string()
+}
+
+/// 設定項目が参照するファイルを開けるか確かめる
+pub fn check_file(key: &str, path: &str) -> Result<(), failure::Error> {
+    match File::open(path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(failure::format_err!("{}: cannot open {}: {}", key, path, e)),
+    }
+}
+
+/// 1つのパス、またはパスの一覧
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum PathList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl PathList {
+    pub fn iter(&self) -> std::slice::Iter<'_, String> {
+        match self {
+            PathList::One(path) => std::slice::from_ref(path).iter(),
+            PathList::Many(paths) => paths.iter(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, String> {
+        match self {
+            PathList::One(path) => std::slice::from_mut(path).iter_mut(),
+            PathList::Many(paths) => paths.iter_mut(),
+        }
+    }
+}
+
+/// 読み込み元の1つ
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Source {
+    /// 複数の読み込み元をまとめる際にテーブル名に付ける接頭辞 (省略時はデータベース名)
+    pub name: Option<String>,
+    pub source: SourceType,
+    pub source_from: String,
+    /// この読み込み元のテーブルのグループ (省略時はデータベース名)
+    pub group: Option<String>,
 }
 
 // #[serde(rename_all = "snake_case")]
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Intermediate {
-    pub save_to: Option<String>
+    pub save_to: Option<String>,
+    /// 保存する形式 (省略時は save_to の拡張子から決め、決まらなければ yaml)
+    pub format: Option<FileFormat>,
+}
+
+impl Intermediate {
+    pub fn get_format(&self) -> FileFormat {
+        self.format
+            .or(self.save_to.as_ref().and_then(|path| FileFormat::from_path(path)))
+            .unwrap_or(FileFormat::Yaml)
+    }
+}
+
+/// 出力する図の1つ
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    /// 出力先。"{group}" を含む場合はグループごとに1つずつ出力する
+    pub path: String,
+    /// 省略時は拡張子から決め (.mmd, .mermaid なら mermaid)、決まらなければ plantuml
+    pub format: Option<OutputFormat>,
+    /// 出力するグループ (省略時はすべて。親グループを指定するとその下のグループも含む)
+    pub group: Option<Vec<String>>,
+    /// 列の表示量 (省略時は summary)
+    pub detail: Option<DetailLevel>,
+    pub plantuml: Option<PlantUmlOption>,
+}
+
+impl Output {
+    pub fn get_format(&self) -> OutputFormat {
+        if let Some(format) = self.format {
+            return format;
+        }
+        let ext = Path::new(&self.path).extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        match ext.as_ref().map(|e| e.as_str()) {
+            Some("mmd") | Some("mermaid") => OutputFormat::Mermaid,
+            _ => OutputFormat::PlantUml,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum OutputFormat {
+    #[serde(rename = "plantuml")]
+    PlantUml,
+    /// Mermaid の erDiagram
+    #[serde(rename = "mermaid")]
+    Mermaid,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum DetailLevel {
+    /// テーブル名だけ
+    #[serde(rename = "tables")]
+    Tables,
+    /// 主キーとリレーションの列だけ
+    #[serde(rename = "keys")]
+    Keys,
+    /// 先頭の3列
+    #[serde(rename = "summary")]
+    Summary,
+    /// すべての列
+    #[serde(rename = "full")]
+    Full,
+}
+
+/// 名前の一部だけが異なる同じ構造のテーブルを1つにまとめるパターン
+/// まとめた後も、ex_info ではまとめる前のテーブル名で指定できる
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TablePattern {
+    /// 変化する部分を1つ目のキャプチャで囲んだ正規表現 ("^log_(\\d{6})$" など)
+    pub pattern: String,
+    /// まとめたテーブル名でキャプチャ部分を置き換える名前 ("yyyymm" なら log_{yyyymm})
+    pub placeholder: Option<String>,
+}
+
+impl TablePattern {
+    /// 変化する部分のキャプチャが無い正規表現はどのテーブルにも一致しないのでエラーにする
+    pub fn regex(&self) -> Result<Regex, failure::Error> {
+        let re = Regex::new(&self.pattern)?;
+        if re.captures_len() < 2 {
+            return Err(failure::format_err!("{} has no capture group for the varying part", self.pattern));
+        }
+        Ok(re)
+    }
+}
+
+/// 読み込むテーブルの絞り込み
+/// 名前は glob ("tmp_*") か、"re:" で始まる正規表現 ("re:^bk_\\d+$") で指定する
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TableFilter {
+    /// 読み込むテーブル (省略時はすべて)
+    pub include: Option<Vec<String>>,
+    /// 読み込まないテーブル (include より優先)
+    pub exclude: Option<Vec<String>>,
+    /// 除外したテーブルへのリレーションの扱い (省略時は drop)
+    pub excluded_relations: Option<ExcludedRelations>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum ExcludedRelations {
+    /// リレーションを削除する
+    #[serde(rename = "drop")]
+    Drop,
+    /// 名前だけのテーブルを置いてリレーションを残す
+    #[serde(rename = "stub")]
+    Stub,
+}
+
+// #[serde(rename_all = "snake_case")]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PlantUmlOption {
+    /// 列挙型の値一覧の出力方法
+    pub enum_values: Option<EnumValueStyle>,
+    /// ユニーク制約に含まれる列に [UQ] を付ける
+    pub mark_unique: Option<bool>,
+    /// 自動採番・生成列に [AI] [IDENTITY] [SEQ] [GEN] を付ける
+    pub mark_generated: Option<bool>,
+    /// チェック制約をエンティティ内に出力する
+    pub show_checks: Option<bool>,
+    /// テーブルのトリガーをエンティティ内に出力する
+    pub show_triggers: Option<bool>,
+    /// トリガーを持つテーブルから、トリガーが更新するテーブルへ点線を引く
+    pub trigger_edges: Option<bool>,
+    /// パーティションを親テーブルのエンティティにまとめ、ノートを付ける
+    pub collapse_partitions: Option<bool>,
+    /// まとめたパーティションの名前と範囲をノートに列挙する
+    pub list_partitions: Option<bool>,
+    /// 行数とサイズをエンティティ内に出力する
+    pub show_statistics: Option<bool>,
+    /// 行数に応じてエンティティに色を付ける
+    pub colour_by_size: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum EnumValueStyle {
+    #[serde(rename = "none")]
+    None,
+    /// エンティティ横のノートとして出力
+    #[serde(rename = "note")]
+    Note,
+    /// enum エンティティとして出力し、参照元テーブルと点線で結ぶ
+    #[serde(rename = "entity")]
+    Entity,
 }
 
 // #[serde(rename_all = "lowercase")] // renameの代わりに使用すれば、すべてを小文字にして出力となる。
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub enum SourceType {
     #[serde(rename = "mysql")]
     MySQL,
@@ -46,8 +371,9 @@ pub enum SourceType {
     PostgreSQL,
     #[serde(rename = "sqlite")]
     SQLite,
-    #[serde(rename = "yaml")]
-    YAML,
+    /// 保存しておいた中間形式ファイル (YAML, JSON, TOML)
+    #[serde(rename = "intermediate", alias = "yaml")]
+    Intermediate,
 }
 
 #[test]
@@ -60,8 +386,135 @@ group:
 - MASTER
 intermediate:
   save_to: "D:\\works\\github\\erdh-clj-doc\\db_intermediate_m.yaml"
-ex-info: "D:\\works\\github\\erdh-clj-doc\\ex_table_info.yaml"
+ex_info: "D:\\works\\github\\erdh-clj-doc\\ex_table_info.yaml"
     "#;
     let c = Config::from_yaml(&yaml);
     assert_eq!(c.is_ok(), true);
 }
+
+#[test]
+fn parse_yaml_plantuml_option_success() {
+    let yaml = r#"
+source: yaml
+source_from: "db_intermediate.yaml"
+plantuml:
+  enum_values: note
+    "#;
+    let c = Config::from_yaml(&yaml).unwrap();
+    assert_eq!(c.plantuml.unwrap().enum_values, Some(EnumValueStyle::Note));
+}
+
+#[test]
+fn parse_yaml_table_filter_success() {
+    let yaml = r#"
+source: yaml
+source_from: "db_intermediate.yaml"
+tables:
+  exclude:
+    - schema_migrations
+    - "tmp_*"
+    - "re:^bk_\\d+$"
+  excluded_relations: stub
+    "#;
+    let c = Config::from_yaml(&yaml).unwrap();
+    let tables = c.tables.unwrap();
+    assert_eq!(tables.exclude.unwrap().len(), 3);
+    assert_eq!(tables.excluded_relations, Some(ExcludedRelations::Stub));
+}
+
+#[test]
+fn parse_yaml_sources_success() {
+    let yaml = r#"
+sources:
+  - name: core
+    source: mysql
+    source_from: db_con_mysql.yaml
+  - name: billing
+    source: postgres
+    source_from: db_con_postgres.yaml
+    group: BILLING
+    "#;
+    let c = Config::from_yaml(&yaml).unwrap();
+    let sources = c.get_sources().unwrap();
+    assert_eq!(sources.len(), 2);
+    assert_eq!(sources[1].group, Some("BILLING".to_string()));
+
+    let yaml = r#"
+source: mysql
+source_from: db_con_mysql.yaml
+sources:
+  - source: postgres
+    source_from: db_con_postgres.yaml
+    "#;
+    assert_eq!(Config::from_yaml(&yaml).unwrap().get_sources().is_err(), true);
+}
+
+#[test]
+fn parse_yaml_unknown_field_error() {
+    let yaml = r#"
+source: mysql
+source_from: db_con_mysql.yaml
+ex-info: ex_table_info.yaml
+    "#;
+    let message = Config::from_yaml(&yaml).unwrap_err().to_string();
+    assert_eq!(message.contains("unknown field `ex-info`"), true, "{}", message);
+    assert_eq!(message.contains("did you mean `ex_info`?"), true, "{}", message);
+}
+
+#[test]
+fn resolve_path_success() {
+    let base = base_dir("configs/erdh/config.yaml");
+    assert_eq!(Path::new(&resolve_path(&base, "db.yaml")), Path::new("configs/erdh/db.yaml"));
+    assert_eq!(Path::new(&resolve_path(&base_dir("config.yaml"), "db.yaml")), Path::new("db.yaml"));
+    let absolute = if cfg!(windows) { "C:\\erdh\\db.yaml" } else { "/erdh/db.yaml" };
+    assert_eq!(resolve_path(&base, absolute), absolute);
+}
+
+#[test]
+fn parse_yaml_outputs_success() {
+    let yaml = r#"
+source: mysql
+source_from: db_con_mysql.yaml
+group: [DATA]
+outputs:
+  - path: overview.puml
+    detail: tables
+  - path: "groups/{group}.mmd"
+  - path: billing.puml
+    group: [BILLING]
+    detail: full
+    plantuml:
+      mark_unique: true
+    "#;
+    let c = Config::from_yaml(&yaml).unwrap();
+    let outputs = c.get_outputs(Some("result.puml")).unwrap();
+    assert_eq!(outputs.len(), 4);
+    assert_eq!(outputs[0].detail, Some(DetailLevel::Tables));
+    assert_eq!(outputs[1].get_format(), OutputFormat::Mermaid);
+    assert_eq!(outputs[2].get_format(), OutputFormat::PlantUml);
+    assert_eq!(outputs[3].group, Some(vec!["DATA".to_string()]));
+
+    let yaml = "source: mysql\nsource_from: db_con_mysql.yaml\n";
+    assert_eq!(Config::from_yaml(&yaml).unwrap().get_outputs(None).is_err(), true);
+}
+
+#[test]
+fn get_ex_info_files_success() {
+    use std::fs;
+    let dir = std::env::temp_dir().join(format!("erdh_ex_info_files_{}", std::process::id()));
+    fs::create_dir_all(dir.join("org")).unwrap();
+    for name in &["org/20_master.yaml", "org/10_base.json", "org/readme.txt", "service.yaml"] {
+        fs::write(dir.join(name), "tables: []\n").unwrap();
+    }
+    fs::write(dir.join("config.yaml"), "source: mysql\nsource_from: db.yaml\nex_info: [org, service.yaml]\n").unwrap();
+    let c = Config::from_file(&dir.join("config.yaml").to_string_lossy()).unwrap();
+    let files: Vec<String> = c.get_ex_info_files().unwrap().iter()
+        .map(|f| Path::new(f).strip_prefix(&dir).unwrap().to_string_lossy().replace('\\', "/"))
+        .collect();
+    assert_eq!(files, vec!["org/10_base.json", "org/20_master.yaml", "service.yaml"]);
+    assert_eq!(c.referenced_files()[1].0, "ex_info[0]");
+
+    let c = Config::from_yaml("source: mysql\nsource_from: db.yaml\nex_info: ex_info.yaml\n").unwrap();
+    assert_eq!(c.ex_info, Some(PathList::One("ex_info.yaml".to_string())));
+    fs::remove_dir_all(&dir).ok();
+}
@@ -1,3 +1,82 @@
 pub mod mysql;
 pub mod postgres;
 pub mod sqlite;
+
+use super::erdh::erdh_data::{Index, push_index_column};
+use std::collections::HashMap;
+extern crate failure;
+#[cfg(feature = "query-count")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "query-count")]
+static QUERY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// カタログへの問い合わせ回数を数える (ベンチマーク用。query-count フィーチャーが無効なら何もしない)
+pub fn count_query() {
+    #[cfg(feature = "query-count")]
+    QUERY_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+/// これまでに発行した問い合わせの回数
+#[cfg(feature = "query-count")]
+pub fn query_count() -> usize {
+    QUERY_COUNT.load(Ordering::SeqCst)
+}
+
+#[cfg(feature = "query-count")]
+pub fn reset_query_count() {
+    QUERY_COUNT.store(0, Ordering::SeqCst);
+}
+
+/// 制限時間を超えて打ち切られたカタログ問い合わせのエラー
+pub fn timeout_error<E: std::fmt::Display>(query: &str, cause: E) -> failure::Error {
+    let summary: Vec<&str> = query.split_whitespace().take(8).collect();
+    failure::format_err!("catalog query exceeded statement_timeout/lock_timeout and was aborted: {} ... ({})",
+        summary.join(" "), cause)
+}
+
+/// (テーブル名, 値) の組をテーブル名ごとにまとめる。テーブル内の順序は保たれる
+pub fn group_by_table<T>(rows: Vec<(String, T)>) -> HashMap<String, Vec<T>> {
+    let mut result: HashMap<String, Vec<T>> = HashMap::new();
+    for (table_name, value) in rows {
+        result.entry(table_name).or_insert_with(|| vec![]).push(value);
+    }
+    result
+}
+
+/// (テーブル名, インデックス名, 列名) の行をテーブルごとの複数列のインデックスにまとめる
+/// 式インデックスの列名は None
+pub fn group_indexes(rows: Vec<(String, String, Option<String>)>) -> HashMap<String, Vec<Index>> {
+    let mut result: HashMap<String, Vec<Index>> = HashMap::new();
+    for (table_name, index_name, column_name) in rows {
+        push_index_column(result.entry(table_name).or_insert_with(|| vec![]), index_name, column_name);
+    }
+    result
+}
+
+#[test]
+fn group_by_table_success() {
+    let rows = vec![
+        ("a".to_string(), 1),
+        ("b".to_string(), 2),
+        ("a".to_string(), 3),
+    ];
+    let grouped = group_by_table(rows);
+    assert_eq!(grouped.len(), 2);
+    assert_eq!(grouped["a"], vec![1, 3]);
+    assert_eq!(grouped["b"], vec![2]);
+}
+
+#[test]
+fn group_indexes_success() {
+    let rows = vec![
+        ("a".to_string(), "PRIMARY".to_string(), Some("id".to_string())),
+        ("a".to_string(), "idx_name".to_string(), Some("last_name".to_string())),
+        ("a".to_string(), "idx_name".to_string(), Some("first_name".to_string())),
+        ("b".to_string(), "idx_expr".to_string(), None),
+    ];
+    let grouped = group_indexes(rows);
+    assert_eq!(grouped["a"].len(), 2);
+    assert_eq!(grouped["a"][1].columns, vec!["last_name", "first_name"]);
+    assert_eq!(grouped["b"][0].columns.len(), 0);
+}
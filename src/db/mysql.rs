@@ -1,32 +1,131 @@
 extern crate mysql;
 extern crate r2d2_mysql;
 extern crate r2d2;
+extern crate failure;
 use mysql as my;
-use super::super::erdh::erdh_data::{Construction, Table, Column, Index, ForeignKey, get_relations_from_foreign_keys};
-use super::super::config::db_config::DbConfig;
+use super::super::erdh::erdh_data::{Construction, Table, Column, Index, ForeignKey, UserType, UserTypeKind, Constraint, ConstraintKind};
+use super::super::erdh::erdh_data::{Trigger, Routine, Partition, TableStatistics, get_relations_from_foreign_keys, push_constraint_column, columns_in_expression};
+use super::super::erdh::erdh_data::{find_modified_tables, find_referenced_tables};
+use super::super::erdh::erdh_data::{KeyGeneration, KeyGenerationKind, ComputedColumn};
+use super::super::erdh::column_type::ColumnType;
+use super::super::erdh::table_filter::TableMatcher;
+use super::super::erdh::migration::FORMAT_VERSION;
+use super::super::config::db_config::{DbConfig, DbType, TlsConfig, SslMode};
+use super::{count_query, group_by_table, group_indexes, timeout_error};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
-pub fn get_opts(config: &DbConfig) -> my::Opts {
+/// use_tls が false の場合は tls の設定を無視する (prefer で TLS に失敗した場合の再接続用)
+pub fn get_opts(config: &DbConfig, use_tls: bool) -> Result<my::Opts, failure::Error> {
     let mut builder = my::OptsBuilder::default();
     let port = config.port.clone().unwrap_or("3306".to_string());
-    builder.user(config.user.clone()).pass(config.password.clone())
+    builder.user(config.get_user()).pass(config.get_password()?)
             .ip_or_hostname(config.host.clone())
-            .tcp_port(port.parse().unwrap())
-            .db_name(config.dbname.clone().unwrap().into());
-    builder.into()
+            .tcp_port(port.parse()?)
+            .db_name(config.dbname.clone());
+    if let Some(socket) = &config.socket {
+        builder.socket(Some(socket.clone())).prefer_socket(true);
+    }
+    // 接続ごとに読み取り専用にし、ロック待ちを制限する
+    let mut init = vec!["SET SESSION TRANSACTION READ ONLY".to_string()];
+    let lock_timeout = config.get_lock_timeout();
+    if lock_timeout > 0 {
+        init.push(format!("SET SESSION lock_wait_timeout = {}", lock_timeout));
+    }
+    builder.init(init);
+    // 接続属性 program_name は performance_schema.session_connect_attrs で確認できる
+    let mut attrs = HashMap::new();
+    attrs.insert("program_name".to_string(), config.get_application_name());
+    builder.connect_attrs(attrs);
+    // サーバー側で打ち切られなかった場合もクライアント側で待つのをやめる
+    let statement_timeout = config.get_statement_timeout();
+    if statement_timeout > 0 {
+        builder.read_timeout(Some(Duration::from_secs(statement_timeout + 5)));
+    }
+    if let Some(tls) = &config.tls {
+        if use_tls && tls.get_mode() != SslMode::Disable {
+            builder.ssl_opts(Some(get_ssl_opts(tls)));
+        }
+    }
+    Ok(builder.into())
+}
+
+fn get_ssl_opts(tls: &TlsConfig) -> my::SslOpts {
+    let mode = tls.get_mode();
+    my::SslOpts::default()
+        .with_root_cert_path(tls.ca_cert.as_ref().map(PathBuf::from))
+        .with_pkcs12_path(tls.client_cert.as_ref().map(PathBuf::from))
+        .with_password(tls.client_cert_password.clone())
+        .with_danger_accept_invalid_certs(mode == SslMode::Prefer || mode == SslMode::Require)
+        .with_danger_skip_domain_validation(mode != SslMode::VerifyFull)
 }
 
-pub fn read_db(config: &DbConfig) -> Construction {
-    let opts = get_opts(&config);
-    let pool = my::Pool::new(opts).unwrap();
-    let db_name = get_db_name_from_pool(&pool).unwrap();
+/// 接続1つだけの接続プールを作る。prefer の場合は TLS で接続できなければ平文で接続し直す
+pub fn get_pool(config: &DbConfig) -> Result<my::Pool, failure::Error> {
+    let prefer = config.tls.as_ref().map(|t| t.get_mode() == SslMode::Prefer).unwrap_or(false);
+    let pool = match my::Pool::new_manual(1, 1, get_opts(config, true)?) {
+        Ok(pool) => pool,
+        Err(_) if prefer => my::Pool::new_manual(1, 1, get_opts(config, false)?)?,
+        Err(e) => return Err(e.into()),
+    };
+    // max_execution_time は MySQL 5.7.8 以降、max_statement_time は MariaDB 10.1 以降にしかないので、
+    // 接続時の init ではなく失敗を無視して設定する (接続は1つなので設定は保たれる)
+    let statement_timeout = config.get_statement_timeout();
+    if statement_timeout > 0 {
+        count_query();
+        if pool.prep_exec(format!("SET SESSION max_execution_time = {}", statement_timeout * 1000), ()).is_err() {
+            count_query();
+            pool.prep_exec(format!("SET SESSION max_statement_time = {}", statement_timeout), ()).ok();
+        }
+    }
+    Ok(pool)
+}
+
+/// スキーマ全体をまとめて問い合わせ、テーブル数によらず一定回数のクエリで読み込む
+pub fn read_db(config: &DbConfig, filter: &TableMatcher) -> Result<Construction, failure::Error> {
+    let pool = get_pool(&config)?;
+    let db_name = get_db_name_from_pool(&pool)?.ok_or(failure::format_err!("no database is selected"))?;
+
+    let mut table_names = collect_table_names(&pool)?;
+    table_names.retain(|t| filter.is_match(t));
+    let triggers = collect_triggers(&pool, &db_name, &table_names)?;
+    let routines = collect_routines(&pool, &db_name, &table_names)?;
 
-    let table_names = collect_table_names(&pool);
+    // 除外したテーブルの詳細は問い合わせない
+    let mut columns_by_table = collect_table_columns(&pool, &db_name, &table_names)?;
+    let mut indexes_by_table = collect_indexes(&pool, &db_name, &table_names)?;
+    let mut foreign_keys_by_table = collect_foregin_keys(&pool, &db_name, &table_names)?;
+    let mut constraints_by_table = collect_constraints(&pool, &db_name, &table_names, &columns_by_table)?;
+    let mut partitions_by_table = collect_partitions(&pool, &db_name, &table_names)?;
+    let mut statistics_by_table = if config.statistics.unwrap_or(true) {
+        collect_statistics(&pool, &db_name, &table_names)?
+    } else {
+        HashMap::new()
+    };
 
     let mut tables = Vec::new();
+    let mut user_types = Vec::new();
     for tbl in table_names {
-        let columns = collect_table_columns(&pool, &db_name, &tbl);
-        let indexes = collect_indexes(&pool, &db_name, &tbl);
-        let foreign_keys = collect_foregin_keys(&pool, &db_name, &tbl);
+        let mut columns = columns_by_table.remove(&tbl).unwrap_or(vec![]);
+        // ENUM/SET 列は "テーブル名.列名" の型として登録する
+        for column in columns.iter_mut() {
+            if let Some((kind, values)) = parse_enum_values(&column.column_type) {
+                let type_name = format!("{}.{}", tbl, column.name);
+                user_types.push(UserType {
+                    name: type_name.clone(),
+                    kind: kind,
+                    base_type: None,
+                    values: values,
+                });
+                column.user_type = Some(type_name);
+            }
+        }
+        let indexes = indexes_by_table.remove(&tbl).unwrap_or(vec![]);
+        let foreign_keys = foreign_keys_by_table.remove(&tbl).unwrap_or(vec![]);
+        let constraints = constraints_by_table.remove(&tbl).unwrap_or(vec![]);
+        let (partition_key, partitions) = partitions_by_table.remove(&tbl).unwrap_or((None, vec![]));
+        let statistics = statistics_by_table.remove(&tbl);
         // foreign_keys から ex_relations を生成
         let ex_relations = get_relations_from_foreign_keys(&foreign_keys);
         tables.push(Table {
@@ -37,104 +136,436 @@ pub fn read_db(config: &DbConfig) -> Construction {
             foreign_keys : foreign_keys,
             ex_relations: ex_relations,
             is_master: None,
+            constraints: constraints,
+            partition_key: partition_key,
+            partitions: partitions,
+            // MySQL のパーティションはテーブルとしては見えない
+            partition_of: None,
+            inherits: vec![],
+            members: vec![],
+            statistics: statistics,
+            is_stub: false,
+            display_name: None,
+            note: None,
+            is_hidden: false,
         });
     }
 
-    Construction {
+    Ok(Construction {
+        format_version: FORMAT_VERSION,
         db_name: db_name,
         tables: tables,
+        user_types: user_types,
+        triggers: triggers,
+        routines: routines,
+        groups: vec![],
+    })
+}
+
+/// クエリを実行して全行を取得する
+fn query_rows<T: my::prelude::FromRow, P: Into<my::Params>>(pool: &my::Pool, query: &str, params: P) -> Result<Vec<T>, failure::Error> {
+    execute_query(pool, query, params).map_err(|e| convert_error(e, query))
+}
+
+/// 古いサーバーに存在しないテーブルへの問い合わせ用。制限時間の超過以外のエラーは空として扱う
+fn query_rows_optional<T: my::prelude::FromRow, P: Into<my::Params>>(pool: &my::Pool, query: &str, params: P) -> Result<Vec<T>, failure::Error> {
+    match execute_query(pool, query, params) {
+        Ok(rows) => Ok(rows),
+        Err(e) => if is_timeout(&e) { Err(convert_error(e, query)) } else { Ok(vec![]) },
     }
 }
 
-pub fn get_db_name_from_pool(pool: &my::Pool) -> Option<String> {
-    match pool.prep_exec("SELECT database() AS db_name", ()).unwrap().next() {
-        Some(Ok(row)) => my::from_row(row),
-        _ => None
+fn execute_query<T: my::prelude::FromRow, P: Into<my::Params>>(pool: &my::Pool, query: &str, params: P) -> my::Result<Vec<T>> {
+    count_query();
+    let mut rows = vec![];
+    for row in pool.prep_exec(query, params)? {
+        rows.push(my::from_row(row?));
     }
+    Ok(rows)
+}
+
+/// 3024: max_execution_time 超過, 1969: MariaDB の max_statement_time 超過, 1205: ロック待ちの超過
+fn is_timeout(e: &my::Error) -> bool {
+    match e {
+        my::Error::MySqlError(e) => e.code == 3024 || e.code == 1969 || e.code == 1205,
+        my::Error::IoError(e) => e.kind() == std::io::ErrorKind::TimedOut || e.kind() == std::io::ErrorKind::WouldBlock,
+        _ => false,
+    }
+}
+
+fn convert_error(e: my::Error, query: &str) -> failure::Error {
+    if is_timeout(&e) {
+        timeout_error(query, e)
+    } else {
+        e.into()
+    }
+}
+
+pub fn get_db_name_from_pool(pool: &my::Pool) -> Result<Option<String>, failure::Error> {
+    let rows: Vec<Option<String>> = query_rows(pool, "SELECT database() AS db_name", ())?;
+    Ok(rows.into_iter().next().and_then(|db_name| db_name))
 }
 
 /// テーブル一覧を取得する
-pub fn collect_table_names(pool: &my::Pool) -> Vec<String> {
-    pool.prep_exec("show tables", ()).map(|result| {
-        result.map(|x| x.unwrap()).map(|row| {
-            let tbl_name = my::from_row(row);
+pub fn collect_table_names(pool: &my::Pool) -> Result<Vec<String>, failure::Error> {
+    query_rows(pool, "show tables", ())
+}
 
-            tbl_name
-        }).collect()
-    }).unwrap()
+/// column を table_names に絞り込む条件と、db_name に続けて渡すパラメータを作る
+/// table_names が空の場合は "IN ()" にならないよう常に偽の条件にする
+fn table_name_filter(column: &str, db_name: &String, table_names: &Vec<String>) -> (String, Vec<my::Value>) {
+    let mut params = vec![my::Value::from(db_name.clone())];
+    if table_names.len() == 0 {
+        return ("AND FALSE".to_string(), params);
+    }
+    params.extend(table_names.iter().map(|t| my::Value::from(t.clone())));
+    (format!("AND {} IN ({})", column, vec!["?"; table_names.len()].join(", ")), params)
 }
 
-pub fn collect_table_columns(pool: &my::Pool, db_name: &String, table_name: &String) -> Vec<Column> {
-    let query = r"
-    SELECT column_name
+pub fn collect_table_columns(pool: &my::Pool, db_name: &String, table_names: &Vec<String>) -> Result<HashMap<String, Vec<Column>>, failure::Error> {
+    let (filter, params) = table_name_filter("c.table_name", db_name, table_names);
+    let query = format!(r"
+    SELECT table_name
+        , column_name
         , column_type
         , column_key
         , extra
         , column_default
         , is_nullable
+        , generation_expression
+        , character_set_name
+        , collation_name
     FROM information_schema.columns c
     WHERE c.table_schema = ?
-    AND c.table_name = ?
-    ORDER BY ordinal_position";
-    pool.prep_exec(query, (db_name, table_name)).map(|result| {
-        result.map(|x| x.unwrap()).map(|row| {
-            let (c_name, c_type, c_key, c_extra, c_default, c_is_nullable) : (String, String, String, String, Option<String>, String)
-            = my::from_row(row);
-            let is_primary = if c_key == "PRI".to_string() { true } else { false };
-            Column {
-                name: c_name,
-                column_type: c_type,
-                key: c_key,
-                extra: c_extra,
-                default: c_default,
-                not_null: if c_is_nullable.to_lowercase() == "true" { false } else { true },
-                is_primary: is_primary,
-            }
-        }).collect()
-    }).unwrap()
+    {}
+    ORDER BY table_name, ordinal_position", filter);
+    let rows: Vec<(String, String, String, String, String, Option<String>, String, Option<String>, Option<String>, Option<String>)>
+        = query_rows(pool, &query, params)?;
+    let rows = rows.into_iter().map(|(table_name, c_name, c_type, c_key, c_extra, c_default, c_is_nullable, c_generation_expression, c_charset, c_collation)| {
+        let mut parsed_type = ColumnType::parse(&DbType::MySQL, &c_type);
+        parsed_type.charset = c_charset;
+        parsed_type.collation = c_collation;
+        let is_primary = if c_key == "PRI".to_string() { true } else { false };
+        let key_generation = if c_extra.to_lowercase().contains("auto_increment") {
+            Some(KeyGeneration {
+                kind: KeyGenerationKind::AutoIncrement,
+                sequence: None,
+            })
+        } else {
+            None
+        };
+        // DEFAULT_GENERATED はデフォルト値が式であることを示すだけなので生成列ではない
+        let extra_upper = c_extra.to_uppercase();
+        let computed = if extra_upper.contains("VIRTUAL GENERATED") || extra_upper.contains("STORED GENERATED") {
+            Some(ComputedColumn {
+                expression: c_generation_expression.unwrap_or("".to_string()),
+                stored: extra_upper.contains("STORED"),
+            })
+        } else {
+            None
+        };
+        (table_name, Column {
+            name: c_name,
+            column_type: c_type,
+            parsed_type: Some(parsed_type),
+            key: c_key,
+            extra: c_extra,
+            default: c_default,
+            not_null: if c_is_nullable.to_lowercase() == "true" { false } else { true },
+            is_primary: is_primary,
+            user_type: None,
+            key_generation: key_generation,
+            computed: computed,
+            display_name: None,
+            note: None,
+            is_hidden: false,
+        })
+    }).collect();
+
+    Ok(group_by_table(rows))
 }
 
-pub fn collect_indexes(pool: &my::Pool, db_name: &String, table_name: &String) -> Vec<Index> {
-    let query = r"
-        SELECT index_name
+pub fn collect_indexes(pool: &my::Pool, db_name: &String, table_names: &Vec<String>) -> Result<HashMap<String, Vec<Index>>, failure::Error> {
+    let (filter, params) = table_name_filter("table_name", db_name, table_names);
+    let query = format!(r"
+        SELECT table_name
+            , index_name
             , column_name
             , seq_in_index
         FROM information_schema.statistics
         WHERE table_schema = ?
-        AND table_name = ?";
-
-    pool.prep_exec(query, (db_name, table_name)).map(|result| {
-        result.map(|x| x.unwrap()).map(|row| {
-            let (index_name, column_name, _seq_in_index) : (String, String, i32) = my::from_row(row);
-            Index {
-                name: index_name,
-                column_name: column_name,
-            }
-        }).collect()
-    }).unwrap()
+        {}
+        ORDER BY table_name, index_name, seq_in_index", filter);
+
+    let rows: Vec<(String, String, Option<String>, i32)> = query_rows(pool, &query, params)?;
+    // 関数インデックスの場合 column_name は NULL になる
+    let rows = rows.into_iter()
+        .map(|(table_name, index_name, column_name, _seq_in_index)| (table_name, index_name, column_name))
+        .collect();
+
+    Ok(group_indexes(rows))
 }
 
-pub fn collect_foregin_keys(pool: &my::Pool, db_name: &String, table_name: &String) -> Vec<ForeignKey> {
-    let query = r"
-        SELECT constraint_name
+pub fn collect_foregin_keys(pool: &my::Pool, db_name: &String, table_names: &Vec<String>) -> Result<HashMap<String, Vec<ForeignKey>>, failure::Error> {
+    let (filter, params) = table_name_filter("table_name", db_name, table_names);
+    let query = format!(r"
+        SELECT table_name
+            , constraint_name
             , column_name
             , referenced_table_name
             , referenced_column_name
         FROM information_schema.key_column_usage
         WHERE constraint_schema = ?
-        AND table_name = ?
         AND constraint_name <> 'PRIMARY'
-        ORDER BY CONSTRAINT_NAME";
-    pool.prep_exec(query, (db_name, table_name)).map(|result| {
-        result.map(|x| x.unwrap()).map(|row| {
-            let (constraint_name, column_name, referenced_table_name, referenced_column_name)
-                 = my::from_row(row);
-            ForeignKey {
-                constraint_name: constraint_name,
-                column_name: column_name,
-                referenced_table_name: referenced_table_name,
-                referenced_column_name: referenced_column_name,
+        AND referenced_table_name IS NOT NULL
+        {}
+        ORDER BY table_name, constraint_name, ordinal_position", filter);
+    let rows: Vec<(String, String, String, String, String)> = query_rows(pool, &query, params)?;
+    let rows = rows.into_iter().map(|(table_name, constraint_name, column_name, referenced_table_name, referenced_column_name)| {
+        (table_name, ForeignKey {
+            constraint_name: constraint_name,
+            column_name: column_name,
+            referenced_table_name: referenced_table_name,
+            referenced_column_name: referenced_column_name,
+        })
+    }).collect();
+
+    Ok(group_by_table(rows))
+}
+
+pub fn collect_constraints(pool: &my::Pool, db_name: &String, table_names: &Vec<String>, columns_by_table: &HashMap<String, Vec<Column>>) -> Result<HashMap<String, Vec<Constraint>>, failure::Error> {
+    let (filter, params) = table_name_filter("tc.table_name", db_name, table_names);
+    let unique_query = format!(r"
+        SELECT tc.table_name
+            , tc.constraint_name
+            , kcu.column_name
+        FROM information_schema.table_constraints tc
+        JOIN information_schema.key_column_usage kcu
+          ON kcu.constraint_schema = tc.constraint_schema
+         AND kcu.table_name = tc.table_name
+         AND kcu.constraint_name = tc.constraint_name
+        WHERE tc.table_schema = ?
+        AND tc.constraint_type = 'UNIQUE'
+        {}
+        ORDER BY tc.table_name, tc.constraint_name, kcu.ordinal_position", filter);
+
+    let mut result: HashMap<String, Vec<Constraint>> = HashMap::new();
+    let rows: Vec<(String, String, String)> = query_rows(pool, &unique_query, params.clone())?;
+    for (table_name, constraint_name, column_name) in rows {
+        let constraints = result.entry(table_name).or_insert_with(|| vec![]);
+        push_constraint_column(constraints, ConstraintKind::Unique, constraint_name, column_name);
+    }
+
+    // check_constraints は MySQL 8.0.16 以降にしか存在しないため、失敗した場合は無視する
+    let check_query = format!(r"
+        SELECT tc.table_name
+            , cc.constraint_name
+            , cc.check_clause
+        FROM information_schema.check_constraints cc
+        JOIN information_schema.table_constraints tc
+          ON tc.constraint_schema = cc.constraint_schema
+         AND tc.constraint_name = cc.constraint_name
+        WHERE tc.table_schema = ?
+        AND tc.constraint_type = 'CHECK'
+        {}
+        ORDER BY tc.table_name, cc.constraint_name", filter);
+    let rows: Vec<(String, String, String)> = query_rows_optional(pool, &check_query, params)?;
+    let no_columns = vec![];
+    for (table_name, constraint_name, check_clause) in rows {
+        let columns = columns_by_table.get(&table_name).unwrap_or(&no_columns);
+        let constraint = Constraint {
+            kind: ConstraintKind::Check,
+            name: constraint_name,
+            columns: columns_in_expression(&check_clause, columns),
+            expression: Some(check_clause),
+        };
+        result.entry(table_name).or_insert_with(|| vec![]).push(constraint);
+    }
+
+    Ok(result)
+}
+
+/// information_schema.tables の推定値から行数とサイズを取得する
+pub fn collect_statistics(pool: &my::Pool, db_name: &String, table_names: &Vec<String>) -> Result<HashMap<String, TableStatistics>, failure::Error> {
+    let (filter, params) = table_name_filter("table_name", db_name, table_names);
+    let query = format!(r"
+        SELECT table_name
+            , table_rows
+            , data_length
+            , index_length
+        FROM information_schema.tables
+        WHERE table_schema = ?
+        {}", filter);
+    let rows: Vec<(String, Option<u64>, Option<u64>, Option<u64>)> = query_rows(pool, &query, params)?;
+    Ok(rows.into_iter().map(|(table_name, table_rows, data_length, index_length)| {
+        (table_name, TableStatistics {
+            row_count: table_rows.map(|v| v as i64),
+            data_size: data_length.map(|v| v as i64),
+            index_size: index_length.map(|v| v as i64),
+            last_analyzed: None,
+        })
+    }).collect())
+}
+
+/// テーブルごとのパーティションキーとパーティションの一覧を取得する
+pub fn collect_partitions(pool: &my::Pool, db_name: &String, table_names: &Vec<String>) -> Result<HashMap<String, (Option<String>, Vec<Partition>)>, failure::Error> {
+    let (filter, params) = table_name_filter("table_name", db_name, table_names);
+    // サブパーティションは親パーティションごとに1行にまとめる
+    let query = format!(r"
+        SELECT table_name
+            , partition_name
+            , partition_method
+            , partition_expression
+            , partition_description
+        FROM information_schema.partitions
+        WHERE table_schema = ?
+        AND partition_name IS NOT NULL
+        AND (subpartition_ordinal_position IS NULL OR subpartition_ordinal_position = 1)
+        {}
+        ORDER BY table_name, partition_ordinal_position", filter);
+    let rows: Vec<(String, String, String, Option<String>, Option<String>)> = query_rows(pool, &query, params)?;
+
+    let mut result: HashMap<String, (Option<String>, Vec<Partition>)> = HashMap::new();
+    for (table_name, name, method, expression, description) in rows {
+        let (partition_key, partitions) = result.entry(table_name).or_insert_with(|| (None, vec![]));
+        if partition_key.is_none() {
+            *partition_key = Some(format!("{} ({})", method, expression.clone().unwrap_or("".to_string())));
+        }
+        let bound = description.map(|d| {
+            if method.starts_with("RANGE") {
+                format!("VALUES LESS THAN ({})", d)
+            } else {
+                format!("VALUES IN ({})", d)
+            }
+        });
+        partitions.push(Partition {
+            name: name,
+            bound: bound,
+        });
+    }
+
+    Ok(result)
+}
+
+pub fn collect_triggers(pool: &my::Pool, db_name: &String, table_names: &Vec<String>) -> Result<Vec<Trigger>, failure::Error> {
+    let query = r"
+        SELECT trigger_name
+            , event_object_table
+            , action_timing
+            , event_manipulation
+            , action_statement
+        FROM information_schema.triggers
+        WHERE trigger_schema = ?
+        ORDER BY event_object_table, action_order";
+    let rows: Vec<(String, String, String, String, String)> = query_rows(pool, query, (db_name,))?;
+    Ok(rows.into_iter().map(|(name, table, timing, event, body)| {
+        let modifies = find_modified_tables(&body, table_names);
+        Trigger {
+            name: name,
+            table: table,
+            timing: timing,
+            event: event,
+            body: body,
+            modifies: modifies,
+        }
+    }).collect())
+}
+
+pub fn collect_routines(pool: &my::Pool, db_name: &String, table_names: &Vec<String>) -> Result<Vec<Routine>, failure::Error> {
+    let query = r"
+        SELECT routine_name
+            , routine_type
+            , routine_definition
+        FROM information_schema.routines
+        WHERE routine_schema = ?
+        ORDER BY routine_name";
+    // 権限が無い場合 routine_definition は NULL になる
+    let rows: Vec<(String, String, Option<String>)> = query_rows(pool, query, (db_name,))?;
+    Ok(rows.into_iter().map(|(name, kind, definition)| {
+        Routine {
+            name: name,
+            kind: kind,
+            referenced_tables: find_referenced_tables(&definition.unwrap_or("".to_string()), table_names),
+        }
+    }).collect())
+}
+
+/// "enum('a','b')" / "set('x','y')" から種別と値の一覧を取り出す
+pub fn parse_enum_values(column_type: &str) -> Option<(UserTypeKind, Vec<String>)> {
+    let lower = column_type.to_lowercase();
+    let (kind, rest) = if lower.starts_with("enum(") {
+        (UserTypeKind::Enum, &column_type[5..])
+    } else if lower.starts_with("set(") {
+        (UserTypeKind::Set, &column_type[4..])
+    } else {
+        return None;
+    };
+
+    let mut values = vec![];
+    let mut current = String::new();
+    let mut in_quote = false;
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quote {
+            if c == '\'' {
+                // '' はエスケープされたクォート
+                if chars.peek() == Some(&'\'') {
+                    current.push('\'');
+                    chars.next();
+                } else {
+                    in_quote = false;
+                    values.push(current.clone());
+                    current.clear();
+                }
+            } else if c == '\\' {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            } else {
+                current.push(c);
             }
-        }).collect()
-    }).unwrap()
+        } else if c == '\'' {
+            in_quote = true;
+        } else if c == ')' {
+            break;
+        }
+    }
+
+    Some((kind, values))
+}
+
+#[test]
+fn parse_enum_values_success() {
+    let (kind, values) = parse_enum_values("enum('male','female','it''s, ok')").unwrap();
+    assert_eq!(kind, UserTypeKind::Enum);
+    assert_eq!(values, vec!["male", "female", "it's, ok"]);
+
+    let (kind, values) = parse_enum_values("set('a','b')").unwrap();
+    assert_eq!(kind, UserTypeKind::Set);
+    assert_eq!(values, vec!["a", "b"]);
+
+    assert_eq!(parse_enum_values("int(11)"), None);
+}
+
+#[test]
+fn table_name_filter_success() {
+    let db_name = "shop".to_string();
+    let (filter, params) = table_name_filter("tc.table_name", &db_name, &vec!["orders".to_string(), "members".to_string()]);
+    assert_eq!(filter, "AND tc.table_name IN (?, ?)");
+    assert_eq!(params, vec![my::Value::from("shop"), my::Value::from("orders"), my::Value::from("members")]);
+
+    let (filter, params) = table_name_filter("table_name", &db_name, &vec![]);
+    assert_eq!(filter, "AND FALSE");
+    assert_eq!(params.len(), 1);
+}
+
+/// ERDH_TEST_MYSQL_CONFIG に DbConfig の YAML のパスを指定して `cargo test -- --ignored` で実行する
+/// 自己署名証明書のサーバーには tls.mode: verify-ca と ca_cert を指定する
+#[test]
+#[ignore]
+fn connect_with_config_from_env() {
+    let path = std::env::var("ERDH_TEST_MYSQL_CONFIG").unwrap();
+    let config = DbConfig::from_yaml_file(&path).unwrap();
+    let pool = get_pool(&config).unwrap();
+    assert_eq!(get_db_name_from_pool(&pool).unwrap().is_some(), true);
 }
@@ -1,55 +1,243 @@
 extern crate sqlite3;
 extern crate nom_sql;
 extern crate regex;
+extern crate failure;
 
-use super::super::erdh::erdh_data::{Construction, Table, Column, ForeignKey, get_relations_from_foreign_keys};
-use super::super::config::db_config::DbConfig;
+use super::super::erdh::erdh_data::{Construction, Table, Column, Index, ForeignKey, Constraint, ConstraintKind, Trigger, TableStatistics};
+use super::super::erdh::erdh_data::{get_relations_from_foreign_keys, columns_in_expression, find_modified_tables};
+use super::super::erdh::erdh_data::{KeyGeneration, KeyGenerationKind, ComputedColumn};
+use super::super::erdh::column_type::ColumnType;
+use super::super::erdh::table_filter::TableMatcher;
+use super::super::erdh::migration::FORMAT_VERSION;
+use super::super::config::db_config::{DbConfig, DbType};
+use super::{count_query, group_indexes};
 use regex::Regex;
+use std::collections::HashMap;
 use std::path::Path;
 
-pub fn read_db(config: &DbConfig) -> Construction {
-    let db_name = config.dbname.clone().unwrap();
-    let conn = sqlite3::open(&db_name).unwrap();
-    let table_create_data = read_table_creates(&conn);
+pub fn read_db(config: &DbConfig, filter: &TableMatcher) -> Result<Construction, failure::Error> {
+    let db_name = config.dbname.clone().ok_or(failure::format_err!("dbname is not set"))?;
+    // 書き込みを避けるため読み取り専用で開く
+    let conn = sqlite3::Connection::open_with_flags(&db_name, sqlite3::OpenFlags::new().set_read_only())?;
+    conn.set_busy_timeout((config.get_lock_timeout() * 1000) as usize)?;
+    let mut table_create_data = read_table_creates(&conn)?;
+    table_create_data.retain(|(t, _)| filter.is_match(t));
+    let table_names: Vec<String> = table_create_data.iter().map(|(name, _)| name.clone()).collect();
 
     let path = Path::new(&db_name);
     let db_file_name = path.file_name().unwrap().to_str().unwrap();
 
+    let mut indexes_by_table = read_indexes(&conn);
+    // COUNT(*) は全件を読むので指定された場合のみ
+    let mut statistics_by_table = if config.statistics.unwrap_or(false) {
+        read_statistics(&conn, &table_names)
+    } else {
+        HashMap::new()
+    };
+
     let mut tables = Vec::new();
 
     for (table_name, create_query) in &table_create_data {
-        if let Some(table) = parse_create_query(&create_query, &db_file_name, &table_name) {
+        if let Some(mut table) = parse_create_query(&create_query, &db_file_name, &table_name) {
+            table.indexes = indexes_by_table.remove(table_name).unwrap_or(vec![]);
+            table.statistics = statistics_by_table.remove(table_name);
             tables.push(table);
         }
     }
-    Construction {
+
+    let triggers = read_triggers(&conn, &table_names)?;
+
+    Ok(Construction {
+        format_version: FORMAT_VERSION,
         db_name: db_file_name.to_string(),
         tables: tables,
-    }
+        user_types: vec![],
+        triggers: triggers,
+        routines: vec![],
+        groups: vec![],
+    })
 }
 
 /// return Vec<(table name, create query)>
-pub fn read_table_creates(conn: &sqlite3::Connection) -> Vec<(String, String)> {
+pub fn read_table_creates(conn: &sqlite3::Connection) -> Result<Vec<(String, String)>, failure::Error> {
     let mut result = vec![];
 
     let query = r#"SELECT tbl_name, sql FROM sqlite_master WHERE type = "table""#;
-    let mut cursor = conn.prepare(query).unwrap().cursor();
+    count_query();
+    let mut cursor = conn.prepare(query)?.cursor();
 
-    while let Some(row) = cursor.next().unwrap() {
+    while let Some(row) = cursor.next()? {
         result.push((row[0].as_string().unwrap().to_string(), row[1].as_string().unwrap().to_string()));
     }
 
+    Ok(result)
+}
+
+pub fn read_triggers(conn: &sqlite3::Connection, table_names: &Vec<String>) -> Result<Vec<Trigger>, failure::Error> {
+    let mut result = vec![];
+
+    let query = r#"SELECT name, tbl_name, sql FROM sqlite_master WHERE type = "trigger""#;
+    count_query();
+    let mut cursor = conn.prepare(query)?.cursor();
+
+    while let Some(row) = cursor.next()? {
+        let sql = row[2].as_string().unwrap();
+        let (timing, event, body) = parse_trigger_sql(sql);
+        result.push(Trigger {
+            name: row[0].as_string().unwrap().to_string(),
+            table: row[1].as_string().unwrap().to_string(),
+            timing: timing,
+            event: event,
+            modifies: find_modified_tables(&body, table_names),
+            body: body,
+        });
+    }
+
+    Ok(result)
+}
+
+/// 全テーブルのインデックスを1回の問い合わせで取得する
+/// テーブル値の pragma 関数が使えない SQLite (3.16 未満) では空を返す
+pub fn read_indexes(conn: &sqlite3::Connection) -> HashMap<String, Vec<Index>> {
+    let query = r#"
+        SELECT m.tbl_name
+             , il.name
+             , ii.name
+          FROM sqlite_master m
+               JOIN pragma_index_list(m.tbl_name) il
+               JOIN pragma_index_info(il.name) ii
+         WHERE m.type = 'table'
+      ORDER BY m.tbl_name, il.name, ii.seqno"#;
+    let rows = query_rows(conn, query, &[]);
+    let rows = rows.into_iter().filter_map(|row| {
+        // 式インデックスの列名は NULL になる
+        let table_name = row[0].as_string()?.to_string();
+        let index_name = row[1].as_string()?.to_string();
+        Some((table_name, index_name, row[2].as_string().map(|s| s.to_string())))
+    }).collect();
+
+    group_indexes(rows)
+}
+
+/// 行数と dbstat によるサイズを取得する
+/// dbstat 仮想テーブルが無効な SQLite ではサイズは None になる
+pub fn read_statistics(conn: &sqlite3::Connection, table_names: &Vec<String>) -> HashMap<String, TableStatistics> {
+    let data_query = r#"
+        SELECT name
+             , SUM(pgsize)
+          FROM dbstat
+      GROUP BY name"#;
+    let data_sizes = query_sizes(conn, data_query);
+
+    let index_query = r#"
+        SELECT m.tbl_name
+             , SUM(s.pgsize)
+          FROM dbstat s
+               JOIN sqlite_master m ON m.name = s.name
+         WHERE m.type = 'index'
+      GROUP BY m.tbl_name"#;
+    let index_sizes = query_sizes(conn, index_query);
+    // dbstat が使える場合、インデックスの無いテーブルは 0 とする
+    let has_dbstat = !data_sizes.is_empty();
+
+    let mut result = HashMap::new();
+    for table_name in table_names {
+        // 行数だけはテーブルごとに数えるしかない
+        let query = format!(r#"SELECT COUNT(*) FROM "{}""#, table_name.replace("\"", "\"\""));
+        let row_count = query_rows(conn, &query, &[]).first().and_then(|row| row[0].as_integer());
+        result.insert(table_name.clone(), TableStatistics {
+            row_count: row_count,
+            data_size: data_sizes.get(table_name).cloned(),
+            index_size: index_sizes.get(table_name).cloned().or(if has_dbstat { Some(0) } else { None }),
+            last_analyzed: None,
+        });
+    }
+
     result
 }
 
+/// (名前, サイズ) を返すクエリを実行する
+fn query_sizes(conn: &sqlite3::Connection, query: &str) -> HashMap<String, i64> {
+    query_rows(conn, query, &[]).into_iter().filter_map(|row| {
+        Some((row[0].as_string()?.to_string(), row[1].as_integer()?))
+    }).collect()
+}
+
+/// クエリを実行して全行を返す。失敗した場合は空を返す
+fn query_rows(conn: &sqlite3::Connection, query: &str, params: &[sqlite3::Value]) -> Vec<Vec<sqlite3::Value>> {
+    count_query();
+    let statement = match conn.prepare(query) {
+        Ok(statement) => statement,
+        Err(_) => return vec![],
+    };
+    let mut cursor = statement.cursor();
+    if cursor.bind(params).is_err() {
+        return vec![];
+    }
+    let mut result = vec![];
+    while let Ok(Some(row)) = cursor.next() {
+        result.push(row.to_vec());
+    }
+
+    result
+}
+
+/// CREATE TRIGGER 文から (タイミング, イベント, 本体) を取り出す
+fn parse_trigger_sql(sql: &str) -> (String, String, String) {
+    let begin = Regex::new(r"(?i)\bBEGIN\b").unwrap();
+    let (header, body) = match begin.find(sql) {
+        Some(m) => (&sql[..m.start()], &sql[m.end()..]),
+        None => (sql, ""),
+    };
+    // 末尾の END を取り除く
+    let end = Regex::new(r"(?i)\bEND\s*;?\s*$").unwrap();
+    let body = end.replace(body, "").trim().to_string();
+
+    let timing_re = Regex::new(r"(?i)\b(BEFORE|AFTER|INSTEAD\s+OF)\b").unwrap();
+    let timing = match timing_re.captures(header) {
+        Some(caps) => caps[1].to_uppercase().split_whitespace().collect::<Vec<_>>().join(" "),
+        // SQLite では省略時は BEFORE
+        None => "BEFORE".to_string(),
+    };
+    let event_re = Regex::new(r"(?i)\b(INSERT|UPDATE|DELETE)\b").unwrap();
+    let event = match event_re.captures(header) {
+        Some(caps) => caps[1].to_uppercase(),
+        None => "".to_string(),
+    };
+
+    (timing, event, body)
+}
+
+#[test]
+fn parse_trigger_sql_success() {
+    let sql = "CREATE TRIGGER log_items AFTER UPDATE OF price ON items BEGIN INSERT INTO item_logs VALUES (new.id); END";
+    let (timing, event, body) = parse_trigger_sql(sql);
+    assert_eq!(timing, "AFTER");
+    assert_eq!(event, "UPDATE");
+    assert_eq!(body, "INSERT INTO item_logs VALUES (new.id);");
+}
+
 fn parse_create_query(create_query: &str, db_name: &str, table_name: &str) -> Option<Table> {
     // 簡易的にコメントを削除
     let re = Regex::new(r"--.+(\r\n|\n)").unwrap();
     let cq = re.replace_all(&create_query, " ");
+    // CHECK 制約はパーサが扱えないので取り除いてから解析する
+    let (cq, checks) = extract_check_constraints(&cq);
+    // 生成列も同様に取り除く
+    let (cq, generated) = extract_generated_columns(&cq);
     let result = match nom_sql::parser::parse_query(&cq) {
         Ok(nom_sql::parser::SqlQuery::CreateTable(q)) => {
             let mut columns = vec![];
+            let mut constraints = vec![];
             for spec in &q.fields {
+                if spec.constraints.contains(&nom_sql::ColumnConstraint::Unique) {
+                    constraints.push(Constraint {
+                        kind: ConstraintKind::Unique,
+                        name: "".to_string(),
+                        columns: vec![spec.column.name.clone()],
+                        expression: None,
+                    });
+                }
                 // spec.sql_type => Text || Int(32) ...
                 let mut default_value = None;
                 for c in &spec.constraints {
@@ -64,11 +252,30 @@ fn parse_create_query(create_query: &str, db_name: &str, table_name: &str) -> Op
                     Column {
                         name: spec.column.name.clone(),
                         column_type: format!("{}", spec.sql_type),
+                        parsed_type: Some(ColumnType::parse(&DbType::SQLite, &format!("{}", spec.sql_type))),
                         key: "".to_string(),
                         extra: "".to_string(),
                         default: default_value,
                         not_null: if spec.constraints.contains(&nom_sql::ColumnConstraint::NotNull) { true } else { false },
                         is_primary: false,
+                        user_type: None,
+                        key_generation: if spec.constraints.contains(&nom_sql::ColumnConstraint::AutoIncrement) {
+                            Some(KeyGeneration {
+                                kind: KeyGenerationKind::AutoIncrement,
+                                sequence: None,
+                            })
+                        } else {
+                            None
+                        },
+                        computed: generated.iter()
+                            .find(|(name, _, _)| name.eq_ignore_ascii_case(&spec.column.name))
+                            .map(|(_, expression, stored)| ComputedColumn {
+                                expression: expression.clone(),
+                                stored: *stored,
+                            }),
+                        display_name: None,
+                        note: None,
+                        is_hidden: false,
                     }
                 );
             }
@@ -92,6 +299,27 @@ fn parse_create_query(create_query: &str, db_name: &str, table_name: &str) -> Op
             // foreign_keys から ex_relations を生成
             let ex_relations = get_relations_from_foreign_keys(&foreign_keys);
 
+            if let Some(ref keys) = &q.keys {
+                for key in keys {
+                    if let nom_sql::TableKey::UniqueKey(name, key_columns) = key {
+                        constraints.push(Constraint {
+                            kind: ConstraintKind::Unique,
+                            name: name.clone().unwrap_or("".to_string()),
+                            columns: key_columns.iter().map(|c| c.name.clone()).collect(),
+                            expression: None,
+                        });
+                    }
+                }
+            }
+            for (name, expression) in checks {
+                constraints.push(Constraint {
+                    kind: ConstraintKind::Check,
+                    name: name.unwrap_or("".to_string()),
+                    columns: columns_in_expression(&expression, &columns),
+                    expression: Some(expression),
+                });
+            }
+
             let table = Table {
                 table: table_name.to_string(),
                 group: db_name.to_string(),
@@ -100,6 +328,17 @@ fn parse_create_query(create_query: &str, db_name: &str, table_name: &str) -> Op
                 foreign_keys: foreign_keys,
                 ex_relations: ex_relations,
                 is_master: None,
+                constraints: constraints,
+                partition_key: None,
+                partitions: vec![],
+                partition_of: None,
+                inherits: vec![],
+                members: vec![],
+                statistics: None,
+                is_stub: false,
+                display_name: None,
+                note: None,
+                is_hidden: false,
             };
 
             Some(table)
@@ -119,3 +358,106 @@ fn parse_create_query(create_query: &str, db_name: &str, table_name: &str) -> Op
 
     result
 }
+
+/// CREATE TABLE 文から CHECK 制約を取り出し、取り除いたSQLと (制約名, 式) の一覧を返す
+fn extract_check_constraints(create_query: &str) -> (String, Vec<(Option<String>, String)>) {
+    let re = Regex::new(r#"(?i)(CONSTRAINT\s+["`]?(\w+)["`]?\s+)?\bCHECK\s*\("#).unwrap();
+    let mut stripped = String::new();
+    let mut checks = vec![];
+    let mut rest = create_query;
+    while let Some(caps) = re.captures(rest) {
+        let whole = caps.get(0).unwrap();
+        let end = match find_closing_paren(&rest[whole.end()..]) {
+            Some(i) => whole.end() + i,
+            None => break,
+        };
+
+        // テーブル制約の場合は直前のカンマも取り除く
+        let before = &rest[..whole.start()];
+        let trimmed = before.trim_end();
+        if trimmed.ends_with(',') {
+            stripped.push_str(&trimmed[..trimmed.len() - 1]);
+        } else {
+            stripped.push_str(before);
+        }
+        checks.push((caps.get(2).map(|m| m.as_str().to_string()), rest[whole.end()..end].trim().to_string()));
+        rest = &rest[end + 1..];
+    }
+    stripped.push_str(rest);
+
+    (stripped, checks)
+}
+
+/// "GENERATED ALWAYS AS (expr) [VIRTUAL|STORED]" を取り出し、取り除いたSQLと (列名, 式, STORED か) の一覧を返す
+fn extract_generated_columns(create_query: &str) -> (String, Vec<(String, String, bool)>) {
+    let re = Regex::new(r"(?i)(GENERATED\s+ALWAYS\s+)?\bAS\s*\(").unwrap();
+    let storage_re = Regex::new(r"(?i)^\s*(VIRTUAL|STORED)\b").unwrap();
+    let mut stripped = String::new();
+    let mut generated = vec![];
+    let mut rest = create_query;
+    while let Some(whole) = re.find(rest) {
+        let end = match find_closing_paren(&rest[whole.end()..]) {
+            Some(i) => whole.end() + i,
+            None => break,
+        };
+        let before = &rest[..whole.start()];
+        // 直前の区切り以降の最初の単語が列名
+        let column_start = before.rfind(|c| c == ',' || c == '(').map(|i| i + 1).unwrap_or(0);
+        let column_name = before[column_start..].split_whitespace().next().unwrap_or("")
+            .trim_matches(|c| c == '`' || c == '"' || c == '[' || c == ']')
+            .to_string();
+        let expression = rest[whole.end()..end].trim().to_string();
+        let mut next = end + 1;
+        let mut stored = false;
+        if let Some(caps) = storage_re.captures(&rest[next..]) {
+            stored = caps[1].eq_ignore_ascii_case("STORED");
+            next += caps.get(0).unwrap().end();
+        }
+        stripped.push_str(before);
+        generated.push((column_name, expression, stored));
+        rest = &rest[next..];
+    }
+    stripped.push_str(rest);
+
+    (stripped, generated)
+}
+
+/// 開き括弧の直後から、対応する閉じ括弧の位置を探す
+fn find_closing_paren(s: &str) -> Option<usize> {
+    let mut depth = 1;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[test]
+fn extract_generated_columns_success() {
+    let sql = "CREATE TABLE items (price INT, num INT, total INT GENERATED ALWAYS AS (price * (num)) STORED, half INT AS (price / 2))";
+    let (stripped, generated) = extract_generated_columns(sql);
+    assert_eq!(stripped, "CREATE TABLE items (price INT, num INT, total INT , half INT )");
+    assert_eq!(generated, vec![
+        ("total".to_string(), "price * (num)".to_string(), true),
+        ("half".to_string(), "price / 2".to_string(), false),
+    ]);
+}
+
+#[test]
+fn extract_check_constraints_success() {
+    let sql = "CREATE TABLE items (id INT, price INT CHECK (price > 0), num INT, CONSTRAINT num_range CHECK (num BETWEEN 0 AND (10)))";
+    let (stripped, checks) = extract_check_constraints(sql);
+    assert_eq!(stripped, "CREATE TABLE items (id INT, price INT , num INT)");
+    assert_eq!(checks, vec![
+        (None, "price > 0".to_string()),
+        (Some("num_range".to_string()), "num BETWEEN 0 AND (10)".to_string()),
+    ]);
+}
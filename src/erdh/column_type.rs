@@ -0,0 +1,295 @@
+use serde_derive::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use super::super::config::db_config::DbType;
+
+/// データベースの方言によらない列の型
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ColumnType {
+    pub family: TypeFamily,
+    /// 文字列・バイナリ型の長さ
+    pub length: Option<u32>,
+    /// 数値型の精度、時刻型の秒の小数部の桁数
+    pub precision: Option<u32>,
+    pub scale: Option<u32>,
+    #[serde(default)]
+    pub unsigned: bool,
+    #[serde(default)]
+    pub array: bool,
+    pub charset: Option<String>,
+    pub collation: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum TypeFamily {
+    #[serde(rename = "boolean")]
+    Boolean,
+    #[serde(rename = "tinyint")]
+    TinyInt,
+    #[serde(rename = "smallint")]
+    SmallInt,
+    #[serde(rename = "integer")]
+    Integer,
+    #[serde(rename = "bigint")]
+    BigInt,
+    #[serde(rename = "decimal")]
+    Decimal,
+    #[serde(rename = "float")]
+    Float,
+    #[serde(rename = "double")]
+    Double,
+    #[serde(rename = "char")]
+    Char,
+    #[serde(rename = "varchar")]
+    Varchar,
+    #[serde(rename = "text")]
+    Text,
+    #[serde(rename = "binary")]
+    Binary,
+    #[serde(rename = "varbinary")]
+    Varbinary,
+    #[serde(rename = "blob")]
+    Blob,
+    #[serde(rename = "date")]
+    Date,
+    #[serde(rename = "time")]
+    Time,
+    #[serde(rename = "datetime")]
+    DateTime,
+    #[serde(rename = "timestamp")]
+    Timestamp,
+    #[serde(rename = "interval")]
+    Interval,
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "uuid")]
+    Uuid,
+    #[serde(rename = "enum")]
+    Enum,
+    #[serde(rename = "set")]
+    Set,
+    #[serde(rename = "other")]
+    Other,
+}
+
+impl ColumnType {
+    /// 各方言の型の表記を解析する
+    /// "int(11) unsigned", "character varying(255)", "numeric(10,2)", "text[]", "INT(32)" など
+    pub fn parse(dialect: &DbType, raw: &str) -> ColumnType {
+        let mut s = raw.trim().to_lowercase();
+
+        let array = s.ends_with("[]");
+        if array {
+            s = s.trim_end_matches("[]").trim().to_string();
+        }
+        let unsigned = s.contains(" unsigned");
+        s = s.replace(" unsigned", "").replace(" zerofill", "");
+
+        // 括弧の中の引数と、括弧を除いた型名
+        let (name, args) = match (s.find('('), s.rfind(')')) {
+            (Some(open), Some(close)) if open < close => {
+                let name = format!("{} {}", &s[..open], &s[close + 1..]);
+                let args: Vec<String> = s[open + 1..close].split(',').map(|a| a.trim().to_string()).collect();
+                (name, args)
+            }
+            _ => (s.clone(), vec![]),
+        };
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let mut family = family_from_name(&name);
+        if *dialect == DbType::MySQL && name == "tinyint" && args == vec!["1"] {
+            // MySQL の BOOLEAN は tinyint(1) として作られる
+            family = TypeFamily::Boolean;
+        }
+        if family == TypeFamily::Other && *dialect == DbType::SQLite {
+            family = family_from_sqlite_affinity(&name);
+        }
+
+        let numbers: Vec<Option<u32>> = args.iter().map(|a| a.parse().ok()).collect();
+        let first = numbers.get(0).cloned().unwrap_or(None);
+        let second = numbers.get(1).cloned().unwrap_or(None);
+        let (length, precision, scale) = match family {
+            TypeFamily::Char | TypeFamily::Varchar | TypeFamily::Binary | TypeFamily::Varbinary => (first, None, None),
+            TypeFamily::Decimal | TypeFamily::Float | TypeFamily::Double => (None, first, second),
+            TypeFamily::Time | TypeFamily::DateTime | TypeFamily::Timestamp => (None, first, None),
+            // 整数型の表示幅などは型としての意味を持たない
+            _ => (None, None, None),
+        };
+
+        ColumnType {
+            family: family,
+            length: length,
+            precision: precision,
+            scale: scale,
+            unsigned: unsigned,
+            array: array,
+            charset: None,
+            collation: None,
+        }
+    }
+
+    /// 指定した方言での型の表記
+    pub fn to_sql(&self, dialect: &DbType) -> String {
+        let base = match (dialect, self.family) {
+            (DbType::SQLite, TypeFamily::Boolean)
+            | (DbType::SQLite, TypeFamily::TinyInt)
+            | (DbType::SQLite, TypeFamily::SmallInt)
+            | (DbType::SQLite, TypeFamily::Integer)
+            | (DbType::SQLite, TypeFamily::BigInt) => "INTEGER",
+            (DbType::SQLite, TypeFamily::Float) | (DbType::SQLite, TypeFamily::Double) => "REAL",
+            (DbType::SQLite, TypeFamily::Binary)
+            | (DbType::SQLite, TypeFamily::Varbinary)
+            | (DbType::SQLite, TypeFamily::Blob) => "BLOB",
+            (DbType::SQLite, TypeFamily::Decimal) => "NUMERIC",
+            (DbType::SQLite, _) => "TEXT",
+            (_, TypeFamily::Boolean) => "BOOLEAN",
+            (DbType::PostgreSQL, TypeFamily::TinyInt) => "SMALLINT",
+            (_, TypeFamily::TinyInt) => "TINYINT",
+            (_, TypeFamily::SmallInt) => "SMALLINT",
+            (_, TypeFamily::Integer) => "INTEGER",
+            (_, TypeFamily::BigInt) => "BIGINT",
+            (_, TypeFamily::Decimal) => "DECIMAL",
+            (DbType::PostgreSQL, TypeFamily::Float) => "REAL",
+            (_, TypeFamily::Float) => "FLOAT",
+            (DbType::PostgreSQL, TypeFamily::Double) => "DOUBLE PRECISION",
+            (_, TypeFamily::Double) => "DOUBLE",
+            (_, TypeFamily::Char) => "CHAR",
+            (_, TypeFamily::Varchar) => "VARCHAR",
+            (_, TypeFamily::Text) => "TEXT",
+            (DbType::PostgreSQL, TypeFamily::Binary)
+            | (DbType::PostgreSQL, TypeFamily::Varbinary)
+            | (DbType::PostgreSQL, TypeFamily::Blob) => "BYTEA",
+            (_, TypeFamily::Binary) => "BINARY",
+            (_, TypeFamily::Varbinary) => "VARBINARY",
+            (_, TypeFamily::Blob) => "BLOB",
+            (_, TypeFamily::Date) => "DATE",
+            (_, TypeFamily::Time) => "TIME",
+            (DbType::PostgreSQL, TypeFamily::DateTime) => "TIMESTAMP",
+            (_, TypeFamily::DateTime) => "DATETIME",
+            (_, TypeFamily::Timestamp) => "TIMESTAMP",
+            (DbType::MySQL, TypeFamily::Interval) => "VARCHAR",
+            (_, TypeFamily::Interval) => "INTERVAL",
+            (DbType::PostgreSQL, TypeFamily::Json) => "JSONB",
+            (_, TypeFamily::Json) => "JSON",
+            (DbType::MySQL, TypeFamily::Uuid) => "CHAR(36)",
+            (_, TypeFamily::Uuid) => "UUID",
+            (DbType::PostgreSQL, TypeFamily::Enum) | (DbType::PostgreSQL, TypeFamily::Set) => "TEXT",
+            (_, TypeFamily::Enum) => "ENUM",
+            (_, TypeFamily::Set) => "SET",
+            (_, TypeFamily::Other) => "TEXT",
+        };
+
+        let mut result = base.to_string();
+        if *dialect != DbType::SQLite {
+            if let Some(length) = self.length {
+                result.push_str(&format!("({})", length));
+            } else if let Some(precision) = self.precision {
+                match self.scale {
+                    Some(scale) => result.push_str(&format!("({},{})", precision, scale)),
+                    None => result.push_str(&format!("({})", precision)),
+                }
+            }
+        }
+        if self.unsigned && *dialect == DbType::MySQL {
+            result.push_str(" UNSIGNED");
+        }
+        if self.array && *dialect == DbType::PostgreSQL {
+            result.push_str("[]");
+        }
+
+        result
+    }
+
+    /// 長さや文字セットを除いて同じ種類の型か
+    pub fn is_same_family(&self, other: &ColumnType) -> bool {
+        self.family == other.family && self.array == other.array
+    }
+}
+
+fn family_from_name(name: &str) -> TypeFamily {
+    match name {
+        "bool" | "boolean" => TypeFamily::Boolean,
+        "tinyint" | "int1" => TypeFamily::TinyInt,
+        "smallint" | "int2" | "smallserial" | "serial2" => TypeFamily::SmallInt,
+        "mediumint" | "int" | "integer" | "int4" | "serial" | "serial4" => TypeFamily::Integer,
+        "bigint" | "int8" | "bigserial" | "serial8" => TypeFamily::BigInt,
+        "decimal" | "numeric" | "dec" | "fixed" | "money" => TypeFamily::Decimal,
+        "float" | "real" | "float4" => TypeFamily::Float,
+        "double" | "double precision" | "float8" => TypeFamily::Double,
+        "char" | "character" | "bpchar" | "nchar" => TypeFamily::Char,
+        "varchar" | "character varying" | "nvarchar" | "varchar2" => TypeFamily::Varchar,
+        "text" | "tinytext" | "mediumtext" | "longtext" | "clob" | "citext" => TypeFamily::Text,
+        "binary" | "bit" => TypeFamily::Binary,
+        "varbinary" | "bit varying" | "varbit" => TypeFamily::Varbinary,
+        "blob" | "tinyblob" | "mediumblob" | "longblob" | "bytea" => TypeFamily::Blob,
+        "date" => TypeFamily::Date,
+        "time" | "time without time zone" | "time with time zone" | "timetz" => TypeFamily::Time,
+        "datetime" => TypeFamily::DateTime,
+        "timestamp" | "timestamp without time zone" | "timestamp with time zone" | "timestamptz" => TypeFamily::Timestamp,
+        "interval" => TypeFamily::Interval,
+        "json" | "jsonb" => TypeFamily::Json,
+        "uuid" => TypeFamily::Uuid,
+        "enum" => TypeFamily::Enum,
+        "set" => TypeFamily::Set,
+        _ => TypeFamily::Other,
+    }
+}
+
+/// SQLite の型アフィニティの規則で分類する
+fn family_from_sqlite_affinity(name: &str) -> TypeFamily {
+    if name.contains("int") {
+        TypeFamily::Integer
+    } else if name.contains("char") || name.contains("clob") || name.contains("text") {
+        TypeFamily::Text
+    } else if name.contains("blob") || name.is_empty() {
+        TypeFamily::Blob
+    } else if name.contains("real") || name.contains("floa") || name.contains("doub") {
+        TypeFamily::Double
+    } else {
+        TypeFamily::Decimal
+    }
+}
+
+#[test]
+fn parse_mysql_type_success() {
+    let t = ColumnType::parse(&DbType::MySQL, "int(10) unsigned");
+    assert_eq!(t.family, TypeFamily::Integer);
+    assert_eq!(t.unsigned, true);
+    assert_eq!(t.length, None);
+
+    let t = ColumnType::parse(&DbType::MySQL, "decimal(10,2)");
+    assert_eq!((t.family, t.precision, t.scale), (TypeFamily::Decimal, Some(10), Some(2)));
+
+    let t = ColumnType::parse(&DbType::MySQL, "tinyint(1)");
+    assert_eq!(t.family, TypeFamily::Boolean);
+
+    let t = ColumnType::parse(&DbType::MySQL, "enum('a','b')");
+    assert_eq!(t.family, TypeFamily::Enum);
+}
+
+#[test]
+fn parse_postgres_type_success() {
+    let t = ColumnType::parse(&DbType::PostgreSQL, "character varying(255)");
+    assert_eq!((t.family, t.length), (TypeFamily::Varchar, Some(255)));
+
+    let t = ColumnType::parse(&DbType::PostgreSQL, "timestamp(3) with time zone");
+    assert_eq!((t.family, t.precision), (TypeFamily::Timestamp, Some(3)));
+
+    let t = ColumnType::parse(&DbType::PostgreSQL, "int4[]");
+    assert_eq!((t.family, t.array), (TypeFamily::Integer, true));
+    assert_eq!(t.to_sql(&DbType::PostgreSQL), "INTEGER[]");
+}
+
+#[test]
+fn parse_sqlite_type_success() {
+    let t = ColumnType::parse(&DbType::SQLite, "INT(32)");
+    assert_eq!(t.family, TypeFamily::Integer);
+
+    let t = ColumnType::parse(&DbType::SQLite, "UNSIGNED BIG INT");
+    assert_eq!(t.family, TypeFamily::Integer);
+
+    let t = ColumnType::parse(&DbType::SQLite, "VARYING CHARACTER(20)");
+    assert_eq!(t.family, TypeFamily::Text);
+    assert_eq!(t.to_sql(&DbType::MySQL), "TEXT");
+
+    assert!(ColumnType::parse(&DbType::SQLite, "TEXT").is_same_family(&ColumnType::parse(&DbType::MySQL, "longtext")));
+}
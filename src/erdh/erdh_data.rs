@@ -1,16 +1,33 @@
 use serde_derive::{Serialize, Deserialize};
+use schemars::JsonSchema;
 extern crate serde_yaml;
 use std::fs::File;
 use std::io::prelude::*;
 extern crate failure;
 use std::collections::HashMap;
+use regex::Regex;
+use super::column_type::ColumnType;
+use super::super::config::DetailLevel;
+use super::migration;
+use super::super::file_format::{self, FileFormat};
 
 
 #[serde(rename_all = "snake_case")]
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Construction {
+    /// 中間形式の版 (migration::FORMAT_VERSION)
+    pub format_version: u64,
     pub db_name: String,
     pub tables: Vec<Table>,
+    #[serde(default)]
+    pub user_types: Vec<UserType>,
+    #[serde(default)]
+    pub triggers: Vec<Trigger>,
+    #[serde(default)]
+    pub routines: Vec<Routine>,
+    /// グループの表示設定 (ex_info の groups)
+    #[serde(default)]
+    pub groups: Vec<Group>,
 }
 
 impl Construction {
@@ -22,13 +39,93 @@ impl Construction {
     }
 
     pub fn from_yaml(yaml: &str) -> Result<Construction, failure::Error> {
-        let result: Construction = serde_yaml::from_str(&yaml)?;
+        Self::from_contents(yaml, FileFormat::Yaml)
+    }
+
+    /// YAML, JSON, TOML のいずれかのファイルから読み込む
+    pub fn from_file(path: &str) -> Result<Construction, failure::Error> {
+        let (contents, format) = file_format::read_file(path)?;
+        Self::from_contents(&contents, format)
+    }
+
+    /// 古い版の中間形式は現在の版に変換して読み込む
+    pub fn from_contents(contents: &str, format: FileFormat) -> Result<Construction, failure::Error> {
+        let value: serde_yaml::Value = format.parse(contents)?;
+        let result: Construction = serde_yaml::from_value(migration::migrate(value)?)?;
         Ok(result)
     }
+
+    pub fn find_user_type(&self, name: &str) -> Option<&UserType> {
+        self.user_types.iter().find(|t| t.name == name)
+    }
+
+    pub fn find_group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|g| g.name == name)
+    }
+}
+
+/// グループのパスの区切り ("Sales/Orders" は Sales の下の Orders)
+pub const GROUP_SEPARATOR: char = '/';
+
+/// group が parent そのものか、その下のグループなら true
+pub fn is_group_in(group: &str, parent: &str) -> bool {
+    group == parent || (group.starts_with(parent) && group[parent.len()..].starts_with(GROUP_SEPARATOR))
+}
+
+/// 絞り込むグループ (None か空ならすべて) のいずれかに group が含まれるなら true
+pub fn is_group_selected(group: &str, filters: Option<&Vec<String>>) -> bool {
+    filters.map_or(true, |f| f.len() == 0 || f.iter().any(|parent| is_group_in(group, parent)))
+}
+
+/// グループの表示設定
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    /// グループのパス ("Sales/Orders")
+    pub name: String,
+    pub display_name: Option<String>,
+    /// "#LightBlue" など (colour とも書ける)
+    #[serde(alias = "colour")]
+    pub color: Option<String>,
+    pub style: Option<PackageStyle>,
+    pub description: Option<String>,
+}
+
+/// PlantUML でグループを囲む図形
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum PackageStyle {
+    #[serde(rename = "package")]
+    Package,
+    #[serde(rename = "rectangle")]
+    Rectangle,
+    #[serde(rename = "frame")]
+    Frame,
+    #[serde(rename = "folder")]
+    Folder,
+    #[serde(rename = "node")]
+    Node,
+    #[serde(rename = "cloud")]
+    Cloud,
+    #[serde(rename = "database")]
+    Database,
+}
+
+impl PackageStyle {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            PackageStyle::Package => "package",
+            PackageStyle::Rectangle => "rectangle",
+            PackageStyle::Frame => "frame",
+            PackageStyle::Folder => "folder",
+            PackageStyle::Node => "node",
+            PackageStyle::Cloud => "cloud",
+            PackageStyle::Database => "database",
+        }
+    }
 }
 
 #[serde(rename_all = "snake_case")]
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Table {
     pub table: String,
     pub group: String,
@@ -37,30 +134,221 @@ pub struct Table {
     pub foreign_keys: Vec<ForeignKey>,
     pub ex_relations: Vec<ExRelation>,
     pub is_master: Option<bool>,
+    /// 主キー以外のユニーク制約とチェック制約
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    /// パーティション分割のキー ("RANGE (created_at)" など)
+    #[serde(default)]
+    pub partition_key: Option<String>,
+    #[serde(default)]
+    pub partitions: Vec<Partition>,
+    /// このテーブルがパーティションの場合の親テーブル
+    #[serde(default)]
+    pub partition_of: Option<String>,
+    /// 継承元のテーブル (PostgreSQL の INHERITS)
+    #[serde(default)]
+    pub inherits: Vec<String>,
+    /// パターンでまとめたテーブルの場合、元のテーブル名の一覧
+    #[serde(default)]
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub statistics: Option<TableStatistics>,
+    /// 読み込み対象外のテーブルへのリレーションを残すための名前だけのテーブル
+    #[serde(default)]
+    pub is_stub: bool,
+    /// 図に表示する名前 (ex_info の display_name)
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// 図に付けるノート (ex_info の note)
+    #[serde(default)]
+    pub note: Option<String>,
+    /// 図に出力しない (ex_info の hide)
+    #[serde(default)]
+    pub is_hidden: bool,
+}
+
+impl Table {
+    /// 外部キーかリレーションの参照元の列なら true
+    pub fn is_relation_column(&self, column_name: &str) -> bool {
+        self.foreign_keys.iter().any(|fk| fk.column_name == column_name)
+            || self.ex_relations.iter().any(|r| r.columns.iter().any(|c| c.from == column_name))
+    }
+
+    /// 図に表示する名前
+    pub fn get_display_name(&self) -> &str {
+        self.display_name.as_ref().unwrap_or(&self.table)
+    }
+
+    /// 図に出力する列と、省略した列の数 (隠した列は数えない)
+    pub fn visible_columns(&self, detail: DetailLevel) -> (Vec<&Column>, usize) {
+        let shown = self.columns.iter().filter(|c| !c.is_hidden);
+        let columns: Vec<&Column> = match detail {
+            DetailLevel::Tables => return (vec![], 0),
+            DetailLevel::Keys => shown.clone().filter(|c| c.is_primary || self.is_relation_column(&c.name)).collect(),
+            DetailLevel::Summary => shown.clone().take(3).collect(),
+            DetailLevel::Full => shown.clone().collect(),
+        };
+        let absent = shown.count() - columns.len();
+        (columns, absent)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TableStatistics {
+    /// 行数 (MySQL, PostgreSQL では統計情報による推定値)
+    pub row_count: Option<i64>,
+    /// データ部分のバイト数
+    pub data_size: Option<i64>,
+    /// インデックスのバイト数
+    pub index_size: Option<i64>,
+    pub last_analyzed: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Partition {
+    pub name: String,
+    /// "FOR VALUES FROM ('2024-01-01') TO ('2024-02-01')" や "VALUES LESS THAN (2024)" など
+    pub bound: Option<String>,
 }
 
 #[serde(rename_all = "snake_case")]
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Column {
     pub name: String,
     #[serde(rename = "type")]
     pub column_type: String,
+    /// column_type を方言によらない形に解析したもの
+    #[serde(default)]
+    pub parsed_type: Option<ColumnType>,
     pub key: String,
     pub extra: String,
     pub default: Option<String>,
     pub not_null: bool,
     pub is_primary: bool,
+    /// 列挙型・ドメイン型を参照している場合はその型名 (Construction::user_types の name)
+    #[serde(default)]
+    pub user_type: Option<String>,
+    /// 自動採番の方法
+    #[serde(default)]
+    pub key_generation: Option<KeyGeneration>,
+    /// 生成列の場合の式
+    #[serde(default)]
+    pub computed: Option<ComputedColumn>,
+    /// 図に表示する名前 (ex_info の display_name)
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// 図に付けるノート (ex_info の note)
+    #[serde(default)]
+    pub note: Option<String>,
+    /// 図に出力しない (ex_info の hide)
+    #[serde(default)]
+    pub is_hidden: bool,
+}
+
+impl Column {
+    /// 図に表示する名前
+    pub fn get_display_name(&self) -> &str {
+        self.display_name.as_ref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct KeyGeneration {
+    pub kind: KeyGenerationKind,
+    /// serial やシーケンスのデフォルト値で採番する場合のシーケンス名
+    pub sequence: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum KeyGenerationKind {
+    /// MySQL の auto_increment, SQLite の AUTOINCREMENT
+    #[serde(rename = "auto_increment")]
+    AutoIncrement,
+    #[serde(rename = "identity_always")]
+    IdentityAlways,
+    #[serde(rename = "identity_by_default")]
+    IdentityByDefault,
+    #[serde(rename = "sequence")]
+    Sequence,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ComputedColumn {
+    pub expression: String,
+    /// STORED なら true, VIRTUAL なら false
+    pub stored: bool,
+}
+
+/// 名前付きの列挙型・ドメイン型
+/// MySQLの ENUM(...)/SET(...) 列は "テーブル名.列名" の型として保持する。
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UserType {
+    pub name: String,
+    pub kind: UserTypeKind,
+    pub base_type: Option<String>,
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum UserTypeKind {
+    #[serde(rename = "enum")]
+    Enum,
+    #[serde(rename = "set")]
+    Set,
+    #[serde(rename = "domain")]
+    Domain,
 }
 
 #[serde(rename_all = "snake_case")]
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Index {
     pub name: String,
-    pub column_name: String,
+    /// インデックスの列 (式インデックスの式の部分は含まない)
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Constraint {
+    pub kind: ConstraintKind,
+    pub name: String,
+    pub columns: Vec<String>,
+    /// チェック制約の式
+    pub expression: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum ConstraintKind {
+    #[serde(rename = "unique")]
+    Unique,
+    #[serde(rename = "check")]
+    Check,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Trigger {
+    pub name: String,
+    pub table: String,
+    /// BEFORE / AFTER / INSTEAD OF
+    pub timing: String,
+    /// INSERT / UPDATE / DELETE (複数の場合は " OR " で連結)
+    pub event: String,
+    pub body: String,
+    /// 本体で INSERT/UPDATE/DELETE しているテーブル
+    pub modifies: Vec<String>,
+}
+
+/// ストアドプロシージャ・関数
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Routine {
+    pub name: String,
+    /// PROCEDURE / FUNCTION
+    pub kind: String,
+    /// 本体に現れるテーブル
+    pub referenced_tables: Vec<String>,
 }
 
 #[serde(rename_all = "snake_case")]
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ForeignKey {
     pub constraint_name: String,
     pub column_name: String,
@@ -69,7 +357,7 @@ pub struct ForeignKey {
 }
 
 #[serde(rename_all = "snake_case")]
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ExRelation {
     pub referenced_table_name: String,
     pub columns: Vec<ExRelationColumn>,
@@ -89,7 +377,7 @@ impl ExRelation {
 }
 
 #[serde(rename_all = "snake_case")]
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ExRelationColumn {
     pub from: String,
     pub to: String,
@@ -104,7 +392,7 @@ impl ExRelationColumn {
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Copy, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
 pub enum Connection {
     #[serde(rename = "one")]
     One,
@@ -143,6 +431,79 @@ pub fn get_relations_from_foreign_keys(foreign_keys: &Vec<ForeignKey>) -> Vec<Ex
     result
 }
 
+/// インデックス名ごとに列をまとめながら追加する
+pub fn push_index_column(indexes: &mut Vec<Index>, name: String, column_name: Option<String>) {
+    let i = match indexes.iter().position(|index| index.name == name) {
+        Some(i) => i,
+        None => {
+            indexes.push(Index {
+                name: name,
+                columns: vec![],
+            });
+            indexes.len() - 1
+        }
+    };
+    if let Some(column_name) = column_name {
+        indexes[i].columns.push(column_name);
+    }
+}
+
+/// 制約名ごとに列をまとめながら追加する
+pub fn push_constraint_column(constraints: &mut Vec<Constraint>, kind: ConstraintKind, name: String, column_name: String) {
+    match constraints.iter().position(|c| c.kind == kind && c.name == name) {
+        Some(i) => constraints[i].columns.push(column_name),
+        None => constraints.push(Constraint {
+            kind: kind,
+            name: name,
+            columns: vec![column_name],
+            expression: None,
+        }),
+    }
+}
+
+/// 式の中に現れる列名を列定義順に返す
+pub fn columns_in_expression(expression: &str, columns: &Vec<Column>) -> Vec<String> {
+    let words: Vec<&str> = expression.split(|c: char| !(c.is_alphanumeric() || c == '_')).collect();
+    columns.iter()
+        .filter(|c| words.iter().any(|w| w.eq_ignore_ascii_case(&c.name)))
+        .map(|c| c.name.clone())
+        .collect()
+}
+
+/// SQLの本体から INSERT/UPDATE/DELETE/REPLACE の対象になっているテーブルを探す
+pub fn find_modified_tables(body: &str, table_names: &Vec<String>) -> Vec<String> {
+    let re = Regex::new(r#"(?i)\b(?:INSERT\s+(?:IGNORE\s+)?INTO|REPLACE\s+INTO|MERGE\s+INTO|DELETE\s+FROM|UPDATE)\s+([`"\[]?[\w.]+[`"\]]?)"#).unwrap();
+    let mut result = vec![];
+    for caps in re.captures_iter(body) {
+        let name = caps[1].trim_matches(|c| c == '`' || c == '"' || c == '[' || c == ']');
+        // スキーマ修飾は取り除く
+        let name = name.rsplit('.').next().unwrap_or(name);
+        if let Some(found) = table_names.iter().find(|t| t.eq_ignore_ascii_case(name)) {
+            if !result.contains(found) {
+                result.push(found.clone());
+            }
+        }
+    }
+    result
+}
+
+/// SQLの本体に現れるテーブルを探す
+pub fn find_referenced_tables(body: &str, table_names: &Vec<String>) -> Vec<String> {
+    let words: Vec<&str> = body.split(|c: char| !(c.is_alphanumeric() || c == '_')).collect();
+    table_names.iter()
+        .filter(|t| words.iter().any(|w| w.eq_ignore_ascii_case(t)))
+        .map(|t| t.clone())
+        .collect()
+}
+
+#[test]
+fn find_modified_tables_success() {
+    let table_names = vec!["members".to_string(), "member_logs".to_string(), "items".to_string()];
+    let body = "BEGIN INSERT INTO `member_logs` (id) SELECT id FROM items; UPDATE public.members SET a = 1; END";
+    assert_eq!(find_modified_tables(body, &table_names), vec!["member_logs", "members"]);
+    assert_eq!(find_referenced_tables(body, &table_names), vec!["members", "member_logs", "items"]);
+}
+
 #[test]
 fn parse_yaml_data_success() {
     let yaml = r#"
@@ -284,3 +645,42 @@ tables:
     let c = Construction::from_yaml(&yaml);
     assert_eq!(c.is_ok(), true);
 }
+
+#[test]
+fn construction_formats_success() {
+    let json = r#"{
+  "db_name": "test",
+  "tables": [
+    {"table": "members", "group": "DATA",
+     "columns": [{"name": "id", "type": "int", "key": "PRI", "extra": "", "default": null, "not_null": true, "is_primary": true}],
+     "indexes": [{"name": "PRIMARY", "column_name": "id"}],
+     "foreign_keys": [], "ex_relations": [], "is_master": null}
+  ]
+}"#;
+    let cons = Construction::from_contents(json, FileFormat::Json).unwrap();
+    assert_eq!(cons.format_version, migration::FORMAT_VERSION);
+    assert_eq!(cons.tables[0].indexes[0].columns, vec!["id"]);
+
+    let toml = FileFormat::Toml.to_string(&cons).unwrap();
+    assert_eq!(FileFormat::detect(&toml), FileFormat::Toml);
+    assert_eq!(Construction::from_contents(&toml, FileFormat::Toml).unwrap(), cons);
+}
+
+#[test]
+fn is_group_selected_success() {
+    assert_eq!(is_group_in("Sales/Orders", "Sales"), true);
+    assert_eq!(is_group_in("Sales", "Sales"), true);
+    assert_eq!(is_group_in("SalesReport", "Sales"), false);
+    assert_eq!(is_group_in("Sales", "Sales/Orders"), false);
+    let filters = vec!["Sales/Orders".to_string(), "Members".to_string()];
+    assert_eq!(is_group_selected("Sales/Orders/Lines", Some(&filters)), true);
+    assert_eq!(is_group_selected("Sales/Invoices", Some(&filters)), false);
+    assert_eq!(is_group_selected("Sales/Invoices", None), true);
+}
+
+#[test]
+fn group_colour_alias_success() {
+    let group: Group = serde_yaml::from_str("{name: DATA, colour: \"#LightBlue\"}").unwrap();
+    assert_eq!(group.color, Some("#LightBlue".to_string()));
+    assert_eq!(serde_yaml::from_str::<Group>("{name: DATA, colr: \"#LightBlue\"}").is_err(), true);
+}
@@ -0,0 +1,166 @@
+use super::erdh_data::{self, Construction, Table};
+use super::super::config::extra_config::ExtraConfig;
+use std::collections::HashMap;
+
+/// ex_info の指定のうち、読み込んだ構造と合わないものを警告として返す
+/// - 存在しないテーブル・列 (remove_relations で指定したテーブルを含む)
+/// - 同じテーブルの重複した指定
+/// - どのテーブルにも一致しない rules
+/// - 外部キーと矛盾するリレーション
+/// - どのテーブルも属さないグループの表示設定
+pub fn validate_ex_info(cons: &Construction, ex: &ExtraConfig) -> Vec<String> {
+    // table_patterns でまとめたテーブルは、まとめる前の名前でも指定できる
+    let mut tables: HashMap<&str, &Table> = HashMap::new();
+    for table in &cons.tables {
+        tables.insert(&table.table, table);
+        for member in &table.members {
+            tables.insert(member, table);
+        }
+    }
+    let mut warnings = vec![];
+
+    for (i, rule) in ex.rules.iter().enumerate() {
+        if let Ok(matchers) = rule.matchers() {
+            if !cons.tables.iter().any(|t| matchers.iter().all(|re| re.is_match(&t.table))) {
+                warnings.push(format!("rules[{}] matches no table", i));
+            }
+        }
+    }
+
+    let mut seen: Vec<&str> = vec![];
+    for et in &ex.tables {
+        if seen.contains(&et.table.as_str()) {
+            warnings.push(format!("table {} is listed more than once", et.table));
+        }
+        seen.push(&et.table);
+
+        let table = match tables.get(et.table.as_str()) {
+            Some(table) => *table,
+            None => {
+                warnings.push(format!("table {} does not exist", et.table));
+                continue;
+            }
+        };
+        let mut column_names: Vec<&String> = et.column_order.iter().flatten().collect();
+        column_names.extend(et.columns.iter().flatten().map(|c| &c.name));
+        for name in column_names {
+            if !has_column(table, name) {
+                warnings.push(format!("{}: column {} does not exist", et.table, name));
+            }
+        }
+
+        for removed in et.remove_relations.iter().flatten() {
+            if removed != "*" && !tables.contains_key(removed.as_str()) {
+                warnings.push(format!("{}: removed relation to {}: table does not exist", et.table, removed));
+            }
+        }
+
+        for relation in et.relations.iter().flatten() {
+            let referenced = tables.get(relation.referenced_table_name.as_str());
+            if referenced.is_none() {
+                warnings.push(format!("{}: referenced table {} does not exist", et.table, relation.referenced_table_name));
+            }
+            for column in &relation.columns {
+                if !has_column(table, &column.from) {
+                    warnings.push(format!("{}: relation to {}: column {}.{} does not exist",
+                        et.table, relation.referenced_table_name, et.table, column.from));
+                }
+                if let Some(referenced) = referenced {
+                    if !has_column(referenced, &column.to) {
+                        warnings.push(format!("{}: relation to {}: column {}.{} does not exist",
+                            et.table, relation.referenced_table_name, relation.referenced_table_name, column.to));
+                    }
+                }
+                for fk in table.foreign_keys.iter().filter(|fk| fk.column_name == column.from) {
+                    if fk.referenced_table_name != relation.referenced_table_name || fk.referenced_column_name != column.to {
+                        warnings.push(format!("{}: relation {} -> {}.{} contradicts foreign key {} ({} -> {}.{})",
+                            et.table, column.from, relation.referenced_table_name, column.to,
+                            fk.constraint_name, fk.column_name, fk.referenced_table_name, fk.referenced_column_name));
+                    }
+                }
+            }
+        }
+    }
+
+    // ex_info 自身で割り当てるグループも数える
+    let mut known_groups: Vec<&String> = cons.tables.iter().map(|t| &t.group).collect();
+    known_groups.extend(ex.rules.iter().filter_map(|r| r.group.as_ref()));
+    known_groups.extend(ex.tables.iter().filter_map(|t| t.group.as_ref()));
+    for group in &ex.groups {
+        if !known_groups.iter().any(|g| erdh_data::is_group_in(g, &group.name)) {
+            warnings.push(format!("group {} has no tables", group.name));
+        }
+    }
+
+    warnings
+}
+
+/// 列を読み込んでいないテーブル (中間形式を手で書いた場合や名前だけのテーブル) は確かめない
+fn has_column(table: &Table, name: &str) -> bool {
+    table.columns.len() == 0 || table.columns.iter().any(|c| c.name == name)
+}
+
+#[test]
+fn validate_ex_info_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - table: member_items
+    group: DATA
+    columns:
+      - {name: id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+      - {name: member_id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: false}
+    indexes: []
+    foreign_keys:
+      - {constraint_name: fk_member, column_name: member_id, referenced_table_name: members, referenced_column_name: id}
+    ex_relations: []
+    is_master: ~
+  - table: members
+    group: DATA
+    columns:
+      - {name: id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+"#;
+    let ex_yaml = r#"
+rules:
+  - prefix: mst_
+    group: MASTER
+tables:
+  - table: member_items
+    relations:
+      - referenced_table_name: members
+        columns: [{from: member_id, to: member_id}]
+        this_conn: many
+        that_conn: one
+      - referenced_table_name: items
+        columns: [{from: item_id, to: id}]
+        this_conn: many
+        that_conn: one
+  - table: member_items
+    column_order: [id, name]
+    remove_relations: ["*", members, item]
+  - table: member
+    group: DATA
+groups:
+  - name: DATA
+  - name: SALES/ORDERS
+"#;
+    let cons = Construction::from_yaml(yaml).unwrap();
+    let ex = ExtraConfig::from_yaml(ex_yaml).unwrap();
+    let warnings = validate_ex_info(&cons, &ex);
+    assert_eq!(warnings, vec![
+        "rules[0] matches no table",
+        "member_items: relation to members: column members.member_id does not exist",
+        "member_items: relation member_id -> members.member_id contradicts foreign key fk_member (member_id -> members.id)",
+        "member_items: referenced table items does not exist",
+        "member_items: relation to items: column member_items.item_id does not exist",
+        "table member_items is listed more than once",
+        "member_items: column name does not exist",
+        "member_items: removed relation to item: table does not exist",
+        "table member does not exist",
+        "group SALES/ORDERS has no tables",
+    ]);
+}
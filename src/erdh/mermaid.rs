@@ -0,0 +1,154 @@
+use super::erdh_data::{Construction, Connection, is_group_selected};
+use super::super::config::DetailLevel;
+use std::io::Write;
+
+/// Mermaid の erDiagram を出力する
+/// グループは絞り込みにだけ使う (erDiagram にはパッケージが無い)
+pub fn write_mermaid<W: Write>(cons: &Construction, writer: &mut W, groups: Option<Vec<String>>, detail: DetailLevel) -> Result<(), Box<dyn std::error::Error>> {
+    writer.write("erDiagram\n".as_bytes())?;
+
+    let usable_tables: Vec<&String> = cons.tables.iter()
+        .filter(|t| !t.is_hidden)
+        .filter(|t| is_group_selected(&t.group, groups.as_ref()))
+        .map(|t| &t.table)
+        .collect();
+
+    for table in &cons.tables {
+        if !usable_tables.contains(&&table.table) {
+            continue;
+        }
+        let (columns, absent_count) = if table.is_stub { (vec![], 0) } else { table.visible_columns(detail) };
+        if let Some(note) = &table.note {
+            writer.write(format!("    %% {}: {}\n", table.get_display_name(), note).as_bytes())?;
+        }
+        writer.write(format!("    {}", to_entity(&table.table, table.get_display_name())).as_bytes())?;
+        if columns.len() == 0 && absent_count == 0 {
+            writer.write("\n".as_bytes())?;
+            continue;
+        }
+        writer.write(" {\n".as_bytes())?;
+        for column in columns {
+            let key = if column.is_primary {
+                " PK"
+            } else if table.is_relation_column(&column.name) {
+                " FK"
+            } else {
+                ""
+            };
+            let comment = match &column.note {
+                Some(note) => format!(" \"{}\"", note.replace('"', "'")),
+                None => "".to_string(),
+            };
+            writer.write(format!("        {} {}{}{}\n", to_type(&column.column_type), to_name(column.get_display_name()), key, comment).as_bytes())?;
+        }
+        if absent_count > 0 {
+            writer.write(format!("        string omitted \"{} more columns\"\n", absent_count).as_bytes())?;
+        }
+        writer.write("    }\n".as_bytes())?;
+    }
+
+    for table in &cons.tables {
+        if !usable_tables.contains(&&table.table) {
+            continue;
+        }
+        for ex_relation in &table.ex_relations {
+            if !usable_tables.contains(&&ex_relation.referenced_table_name) {
+                continue;
+            }
+            let label: Vec<&str> = ex_relation.columns.iter().map(|c| c.from.as_str()).collect();
+            writer.write(format!("    {} {}--{} {} : \"{}\"\n",
+                to_name(&table.table),
+                get_this_cardinality(&ex_relation.this_conn),
+                get_that_cardinality(&ex_relation.that_conn),
+                to_name(&ex_relation.referenced_table_name),
+                label.join(", ")).as_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// 名前を Mermaid の識別子として使える文字列に変換する
+fn to_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect()
+}
+
+/// 識別子にできない名前や表示名は "識別子["表示名"]" の形で表示する
+fn to_entity(name: &str, display_name: &str) -> String {
+    let alias = to_name(name);
+    if alias == display_name {
+        alias
+    } else {
+        format!("{}[\"{}\"]", alias, display_name)
+    }
+}
+
+/// "decimal(10,2) unsigned" のような型を1語にする
+fn to_type(column_type: &str) -> String {
+    column_type.trim().chars()
+        .map(|c| if c.is_alphanumeric() || "_-()[]".contains(c) { c } else { '_' })
+        .collect()
+}
+
+fn get_this_cardinality(conn: &Connection) -> &'static str {
+    match conn {
+        Connection::One | Connection::OnlyOne => "||",
+        Connection::ZeroOrOne => "|o",
+        Connection::Many | Connection::ZeroMany => "}o",
+        Connection::OneMore => "}|",
+    }
+}
+
+fn get_that_cardinality(conn: &Connection) -> &'static str {
+    match conn {
+        Connection::One | Connection::OnlyOne => "||",
+        Connection::ZeroOrOne => "o|",
+        Connection::Many | Connection::ZeroMany => "o{",
+        Connection::OneMore => "|{",
+    }
+}
+
+#[test]
+fn write_mermaid_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - table: orders
+    group: DATA
+    columns:
+      - {name: id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+      - {name: member_id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: false}
+      - {name: total, type: "decimal(10,2)", key: "", extra: "", default: ~, not_null: true, is_primary: false}
+    indexes: []
+    foreign_keys: []
+    ex_relations:
+      - {referenced_table_name: core.members, columns: [{from: member_id, to: id}], this_conn: many, that_conn: one}
+    is_master: ~
+  - table: core.members
+    group: MASTER
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+"#;
+    let cons = Construction::from_yaml(yaml).unwrap();
+    let mut out = vec![];
+    write_mermaid(&cons, &mut out, None, DetailLevel::Keys).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out, r#"erDiagram
+    orders {
+        int id PK
+        int member_id FK
+        string omitted "1 more columns"
+    }
+    core_members["core.members"]
+    orders }o--|| core_members : "member_id"
+"#);
+
+    let mut out = vec![];
+    write_mermaid(&cons, &mut out, Some(vec!["DATA".to_string()]), DetailLevel::Full).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.contains("decimal(10_2) total"), true, "{}", out);
+    assert_eq!(out.contains("core_members"), false, "{}", out);
+}
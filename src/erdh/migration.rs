@@ -0,0 +1,118 @@
+//! 中間形式ファイルの版を上げる
+//!
+//! 版ごとの変更:
+//! 1. format_version が無い版
+//! 2. Index が column_name (1列) から columns (複数列) になった
+use serde_yaml::{Mapping, Value};
+extern crate failure;
+
+/// 現在の中間形式の版
+pub const FORMAT_VERSION: u64 = 2;
+
+/// format_version を返す。無い場合は 1
+pub fn read_format_version(value: &Value) -> Result<u64, failure::Error> {
+    match value.get("format_version") {
+        None => Ok(1),
+        Some(v) => v.as_u64().ok_or(failure::format_err!("format_version must be a positive integer: {:?}", v)),
+    }
+}
+
+/// 古い版の中間形式を現在の版に変換する
+pub fn migrate(mut value: Value) -> Result<Value, failure::Error> {
+    let version = read_format_version(&value)?;
+    if version > FORMAT_VERSION {
+        return Err(failure::format_err!(
+            "format_version {} is newer than this erdh supports ({}); upgrade erdh", version, FORMAT_VERSION));
+    }
+    if version < 2 {
+        migrate_1_to_2(&mut value);
+    }
+    if let Some(map) = value.as_mapping_mut() {
+        map.insert(key("format_version"), Value::Number(FORMAT_VERSION.into()));
+    }
+
+    Ok(value)
+}
+
+fn key(name: &str) -> Value {
+    Value::String(name.to_string())
+}
+
+/// 1列ずつの Index を名前ごとの複数列の Index にまとめる
+/// 当時は省略できた配列の項目も補う
+fn migrate_1_to_2(value: &mut Value) {
+    let tables = match value.get_mut("tables").and_then(|t| t.as_sequence_mut()) {
+        Some(tables) => tables,
+        None => return,
+    };
+    for table in tables.iter_mut() {
+        let table = match table.as_mapping_mut() {
+            Some(table) => table,
+            None => continue,
+        };
+        for name in &["columns", "indexes", "foreign_keys", "ex_relations"] {
+            if table.get(&key(name)).map_or(true, |v| v.is_null()) {
+                table.insert(key(name), Value::Sequence(vec![]));
+            }
+        }
+
+        let old_indexes = match table.get(&key("indexes")).and_then(|v| v.as_sequence()) {
+            Some(indexes) => indexes.clone(),
+            None => continue,
+        };
+        let mut indexes: Vec<(String, Vec<Value>)> = vec![];
+        for index in old_indexes {
+            let name = index.get("name").and_then(|v| v.as_str()).unwrap_or("").to_string();
+            // 式インデックスの列名は空文字列になっていた
+            let column = index.get("column_name").and_then(|v| v.as_str()).filter(|c| !c.is_empty());
+            let columns = match indexes.iter().position(|(n, _)| n == &name) {
+                Some(i) => &mut indexes[i].1,
+                None => {
+                    indexes.push((name, vec![]));
+                    &mut indexes.last_mut().unwrap().1
+                }
+            };
+            if let Some(column) = column {
+                columns.push(Value::String(column.to_string()));
+            }
+        }
+        let indexes = indexes.into_iter().map(|(name, columns)| {
+            let mut index = Mapping::new();
+            index.insert(key("name"), Value::String(name));
+            index.insert(key("columns"), Value::Sequence(columns));
+            Value::Mapping(index)
+        }).collect();
+        table.insert(key("indexes"), Value::Sequence(indexes));
+    }
+}
+
+#[test]
+fn migrate_1_to_2_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - table: members
+    group: DATA
+    columns: []
+    indexes:
+      - {name: PRIMARY, column_name: id}
+      - {name: idx_name, column_name: last_name}
+      - {name: idx_name, column_name: first_name}
+      - {name: idx_expr, column_name: ""}
+    is_master: ~
+"#;
+    let value: Value = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(read_format_version(&value).unwrap(), 1);
+    let value = migrate(value).unwrap();
+    assert_eq!(read_format_version(&value).unwrap(), FORMAT_VERSION);
+
+    let table = &value["tables"][0];
+    assert_eq!(table["indexes"].as_sequence().unwrap().len(), 3);
+    assert_eq!(table["indexes"][1]["name"], key("idx_name"));
+    assert_eq!(table["indexes"][1]["columns"].as_sequence().unwrap().len(), 2);
+    assert_eq!(table["indexes"][2]["columns"].as_sequence().unwrap().len(), 0);
+    assert_eq!(table["foreign_keys"].as_sequence().unwrap().len(), 0);
+
+    let value: Value = serde_yaml::from_str("format_version: 99\ndb_name: test\ntables: []\n").unwrap();
+    assert_eq!(migrate(value).is_err(), true);
+}
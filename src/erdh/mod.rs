@@ -1,70 +1,769 @@
-use super::config::{Config, SourceType};
+use super::config::{Config, Source, SourceType, TablePattern, ExcludedRelations, check_file};
+use super::config::{Output, OutputFormat, DetailLevel};
 use super::config::db_config::DbConfig;
-use super::config::extra_config::ExtraConfig;
+use super::config::extra_config::{self, ExtraConfig};
+use std::collections::HashMap;
 
 pub mod erdh_data;
 pub mod plantuml;
+pub mod mermaid;
+pub mod column_type;
+pub mod table_filter;
+pub mod migration;
+pub mod ex_info_check;
+pub mod scaffold;
 
-pub fn get_construction(config: &Config) -> Option<erdh_data::Construction> {
-    match config.source {
-        SourceType::YAML => Some(
-            erdh_data::Construction::from_yaml_file(&config.source_from).unwrap()
-            ),
-        SourceType::MySQL => {
-            let db_config = DbConfig::from_yaml_file(&config.source_from);
-            if db_config.is_ok() {
-                Some(super::db::mysql::read_db(&db_config.unwrap()))
-            } else {
-                None
+use table_filter::TableMatcher;
+
+pub fn get_construction(config: &Config) -> Result<erdh_data::Construction, failure::Error> {
+    for (key, path) in config.referenced_files().iter().filter(|(key, _)| key.starts_with("ex_info")) {
+        check_file(key, path)?;
+    }
+    read_construction(config)
+}
+
+/// ex_info があるかは確かめずに読み込み元だけを読む (scaffold では ex_info をこれから作る)
+pub fn read_construction(config: &Config) -> Result<erdh_data::Construction, failure::Error> {
+    let sources = config.get_sources()?;
+    for (key, path) in config.referenced_files().iter().filter(|(key, _)| !key.starts_with("ex_info")) {
+        check_file(key, path)?;
+    }
+    let matcher = TableMatcher::new(config.tables.as_ref())?;
+    let excluded_relations = config.tables.as_ref()
+        .and_then(|t| t.excluded_relations)
+        .unwrap_or(ExcludedRelations::Drop);
+
+    let mut constructions = vec![];
+    for source in &sources {
+        let mut cons = read_source(source, &matcher)?;
+        table_filter::apply_table_filter(&mut cons, &matcher, excluded_relations);
+        if let Some(group) = &source.group {
+            for table in cons.tables.iter_mut() {
+                table.group = group.clone();
             }
+        }
+        let name = source.name.clone().unwrap_or(cons.db_name.clone());
+        constructions.push((name, cons));
+    }
+
+    // 読み込み元が1つならテーブル名はそのまま
+    if constructions.len() == 1 {
+        return Ok(constructions.pop().unwrap().1);
+    }
+    merge_constructions(constructions)
+}
+
+fn read_source(source: &Source, matcher: &TableMatcher) -> Result<erdh_data::Construction, failure::Error> {
+    let path = &source.source_from;
+    let read_db: fn(&DbConfig, &TableMatcher) -> Result<erdh_data::Construction, failure::Error> = match source.source {
+        SourceType::Intermediate => {
+            return erdh_data::Construction::from_file(path).map_err(|e| failure::format_err!("{}: {}", path, e));
         },
-        SourceType::PostgreSQL => {
-            let db_config = DbConfig::from_yaml_file(&config.source_from);
-            if db_config.is_ok() {
-                Some(super::db::postgres::read_db(&db_config.unwrap()))
-            } else {
-                None
+        SourceType::MySQL => super::db::mysql::read_db,
+        SourceType::PostgreSQL => super::db::postgres::read_db,
+        SourceType::SQLite => super::db::sqlite::read_db,
+    };
+
+    let db_config = DbConfig::from_file(path).map_err(|e| failure::format_err!("{}: {}", path, e))?;
+    for (key, referenced) in db_config.referenced_files() {
+        check_file(&key, &referenced).map_err(|e| failure::format_err!("{}: {}", path, e))?;
+    }
+    read_db(&db_config, matcher)
+}
+
+/// 複数の読み込み元を1つにまとめる
+/// テーブル名とユーザー定義型の名前には "読み込み元の名前." を付ける
+fn merge_constructions(constructions: Vec<(String, erdh_data::Construction)>) -> Result<erdh_data::Construction, failure::Error> {
+    let mut names: Vec<String> = vec![];
+    for (name, _) in &constructions {
+        if names.contains(name) {
+            return Err(failure::format_err!("duplicate source name: {} (set a distinct name for each source)", name));
+        }
+        names.push(name.clone());
+    }
+
+    let mut result = erdh_data::Construction {
+        format_version: migration::FORMAT_VERSION,
+        db_name: names.join(", "),
+        tables: vec![],
+        user_types: vec![],
+        triggers: vec![],
+        routines: vec![],
+        groups: vec![],
+    };
+    for (name, mut cons) in constructions {
+        prefix_table_names(&mut cons, &name);
+        result.tables.append(&mut cons.tables);
+        result.user_types.append(&mut cons.user_types);
+        result.triggers.append(&mut cons.triggers);
+        result.routines.append(&mut cons.routines);
+        result.groups.append(&mut cons.groups);
+    }
+
+    Ok(result)
+}
+
+fn prefix_table_names(cons: &mut erdh_data::Construction, prefix: &str) {
+    let prefixed = |name: &str| format!("{}.{}", prefix, name);
+    let renames: HashMap<String, String> = cons.tables.iter()
+        .map(|t| (t.table.clone(), prefixed(&t.table)))
+        .collect();
+    rename_table_references(cons, &renames);
+    for table in cons.tables.iter_mut() {
+        table.table = prefixed(&table.table);
+        for partition in table.partitions.iter_mut() {
+            partition.name = prefixed(&partition.name);
+        }
+        for column in table.columns.iter_mut() {
+            if let Some(user_type) = column.user_type.as_mut() {
+                *user_type = prefixed(user_type);
             }
+        }
+    }
+    for user_type in cons.user_types.iter_mut() {
+        user_type.name = prefixed(&user_type.name);
+    }
+}
+
+/// 出力先のパスと出力するグループの組
+/// パスに "{group}" を含む場合は対象のグループごとに分ける ("Sales/Orders" は "Sales_Orders" にする)
+pub fn expand_output(cons: &erdh_data::Construction, output: &Output) -> Vec<(String, Option<Vec<String>>)> {
+    if !output.path.contains("{group}") {
+        return vec![(output.path.clone(), output.group.clone())];
+    }
+    let mut groups: Vec<&String> = vec![];
+    for table in &cons.tables {
+        let selected = erdh_data::is_group_selected(&table.group, output.group.as_ref());
+        if selected && !groups.contains(&&table.group) {
+            groups.push(&table.group);
+        }
+    }
+    groups.into_iter()
+        .map(|group| (output.path.replace("{group}", &group.replace(erdh_data::GROUP_SEPARATOR, "_")), Some(vec![group.clone()])))
+        .collect()
+}
+
+pub fn write_output<W: std::io::Write>(cons: &erdh_data::Construction, output: &Output, groups: Option<Vec<String>>, writer: &mut W) -> Result<(), failure::Error> {
+    let detail = output.detail.unwrap_or(DetailLevel::Summary);
+    let result = match output.get_format() {
+        OutputFormat::PlantUml => {
+            let option = output.plantuml.clone().unwrap_or_default();
+            plantuml::write_puml(cons, writer, groups, &option, detail)
         },
-        SourceType::SQLite => {
-            let db_config = DbConfig::from_yaml_file(&config.source_from);
-            if db_config.is_ok() {
-                Some(super::db::sqlite::read_db(&db_config.unwrap()))
-            } else {
-                None
+        OutputFormat::Mermaid => mermaid::write_mermaid(cons, writer, groups, detail),
+    };
+    result.map_err(|e| failure::format_err!("{}", e))
+}
+
+/// ex_info の rules を適用してから、テーブル名で指定した内容で上書きする
+/// table_patterns でまとめたテーブルは、まとめる前のテーブル名でも指定できる
+pub fn apply_ex_info(cons: &mut erdh_data::Construction, ex: &ExtraConfig) -> Result<(), failure::Error> {
+    // 優先度の高い順 (同じなら記述順) に並べる
+    let mut rules = vec![];
+    for (i, rule) in ex.rules.iter().enumerate() {
+        let matchers = rule.matchers().map_err(|e| failure::format_err!("rules[{}]: {}", i, e))?;
+        rules.push((rule, matchers));
+    }
+    rules.sort_by_key(|(rule, _)| -rule.priority.unwrap_or(0));
+
+    for group in &ex.groups {
+        match cons.groups.iter_mut().find(|g| g.name == group.name) {
+            Some(existing) => *existing = group.clone(),
+            None => cons.groups.push(group.clone()),
+        }
+    }
+
+    let mut exact: HashMap<&str, Vec<&extra_config::Table>> = HashMap::new();
+    for et in &ex.tables {
+        exact.entry(et.table.as_str()).or_insert_with(|| vec![]).push(et);
+    }
+
+    // table_patterns でまとめたテーブルは、元のテーブル名での指定も受け付ける
+    let mut collapsed_names: HashMap<String, String> = HashMap::new();
+    for table in &cons.tables {
+        for member in &table.members {
+            collapsed_names.insert(member.clone(), table.table.clone());
+        }
+    }
+    let resolve = |name: &String| collapsed_names.get(name).cloned().unwrap_or(name.clone());
+
+    for table in cons.tables.iter_mut() {
+        let matched: Vec<&extra_config::Rule> = rules.iter()
+            .filter(|(_, matchers)| matchers.iter().all(|re| re.is_match(&table.table)))
+            .map(|(rule, _)| *rule)
+            .collect();
+        if let Some(is_master) = matched.iter().find_map(|r| r.is_master) {
+            table.is_master = Some(is_master);
+        }
+        if let Some(group) = matched.iter().find_map(|r| r.group.as_ref()) {
+            table.group = group.clone();
+        }
+        if let Some(hide) = matched.iter().find_map(|r| r.hide) {
+            table.is_hidden = hide;
+        }
+
+        // まとめる前の名前での指定を先に、まとめた後の名前での指定を後に適用する
+        let mut entries: Vec<&extra_config::Table> = vec![];
+        for name in table.members.iter().chain(std::iter::once(&table.table)) {
+            entries.extend(exact.get(name.as_str()).into_iter().flatten());
+        }
+        for et in entries {
+            if let Some(is_master) = &et.is_master {
+                table.is_master = Some(is_master.clone());
             }
-        },
+            if let Some(group) = &et.group {
+                table.group = group.clone();
+            }
+            // 同じ指定の中では remove_relations を先に適用し、relations で書いたものは残す
+            if let Some(removed) = &et.remove_relations {
+                let remove_all = removed.iter().any(|r| r == "*");
+                let removed: Vec<String> = removed.iter().map(|r| resolve(r)).collect();
+                table.ex_relations.retain(|r| !remove_all && !removed.contains(&r.referenced_table_name));
+            }
+            if let Some(relations) = &et.relations {
+                for r in relations {
+                    let mut r = r.get_clone();
+                    r.referenced_table_name = resolve(&r.referenced_table_name);
+                    let existing = table.ex_relations.iter_mut()
+                        .filter(|e| e.referenced_table_name == r.referenced_table_name);
+                    let mut found = false;
+                    for e in existing {
+                        *e = r.get_clone();
+                        found = true;
+                    }
+                    if !found {
+                        table.ex_relations.push(r);
+                    }
+                }
+            }
+            if let Some(hide) = et.hide {
+                table.is_hidden = hide;
+            }
+            if let Some(display_name) = &et.display_name {
+                table.display_name = Some(display_name.clone());
+            }
+            if let Some(note) = &et.note {
+                table.note = Some(note.clone());
+            }
+            if let Some(order) = &et.column_order {
+                reorder_columns(&mut table.columns, order);
+            }
+            for ec in et.columns.iter().flatten() {
+                for column in table.columns.iter_mut().filter(|c| c.name == ec.name) {
+                    if let Some(hide) = ec.hide {
+                        column.is_hidden = hide;
+                    }
+                    if let Some(display_name) = &ec.display_name {
+                        column.display_name = Some(display_name.clone());
+                    }
+                    if let Some(note) = &ec.note {
+                        column.note = Some(note.clone());
+                    }
+                }
+            }
+        }
     }
+
+    Ok(())
+}
+
+/// order に書いた列を先頭に並べ、残りは元の順序のまま後ろに置く
+fn reorder_columns(columns: &mut Vec<erdh_data::Column>, order: &Vec<String>) {
+    let mut rest = std::mem::replace(columns, vec![]);
+    for name in order {
+        if let Some(i) = rest.iter().position(|c| &c.name == name) {
+            columns.push(rest.remove(i));
+        }
+    }
+    columns.append(&mut rest);
 }
 
-pub fn apply_ex_info(cons: &mut erdh_data::Construction, ex: &ExtraConfig) {
-    let max = cons.tables.len();
-    for i in 0..max {
-        for et in &ex.tables {
-            if cons.tables[i].table == et.table {
-                if let Some(is_master) = &et.is_master {
-                    cons.tables[i].is_master = Some(is_master.clone());
+/// パターンに一致する同じ構造のテーブルを1つのテーブルにまとめる
+/// 列が他と異なるテーブルはまとめずに残し、警告を返す
+pub fn collapse_table_patterns(cons: &mut erdh_data::Construction, patterns: &Vec<TablePattern>) -> Result<Vec<String>, failure::Error> {
+    let mut warnings = vec![];
+    for pattern in patterns {
+        let re = pattern.regex()?;
+        let placeholder = pattern.placeholder.clone().unwrap_or("*".to_string());
+
+        // まとめた後の名前ごとのテーブル (出現順)
+        let mut candidates: Vec<(String, Vec<usize>)> = vec![];
+        for (i, table) in cons.tables.iter().enumerate() {
+            if table.members.len() > 0 {
+                continue;
+            }
+            let m = match re.captures(&table.table).and_then(|caps| caps.get(1)) {
+                Some(m) => m,
+                None => continue,
+            };
+            let merged_name = format!("{}{{{}}}{}", &table.table[..m.start()], placeholder, &table.table[m.end()..]);
+            match candidates.iter().position(|(name, _)| *name == merged_name) {
+                Some(j) => candidates[j].1.push(i),
+                None => candidates.push((merged_name, vec![i])),
+            }
+        }
+
+        // 元のテーブル名 => まとめた後の名前
+        let mut renames = HashMap::new();
+        // まとめた後の名前 => (代表のテーブル名, 元のテーブル名の一覧)
+        let mut merged = HashMap::new();
+        for (merged_name, indexes) in candidates {
+            if indexes.len() < 2 {
+                continue;
+            }
+            // 最も多くのテーブルが持つ列の組み合わせを基準にする (同数なら先に現れたもの)
+            let mut signatures: Vec<(usize, Vec<usize>)> = vec![];
+            for &i in &indexes {
+                match signatures.iter().position(|(base, _)| has_same_columns(&cons.tables[*base], &cons.tables[i])) {
+                    Some(j) => signatures[j].1.push(i),
+                    None => signatures.push((i, vec![i])),
                 }
-                if let Some(group) = &et.group {
-                    cons.tables[i].group = group.clone();
+            }
+            let mut most_common = 0;
+            for (j, (_, same)) in signatures.iter().enumerate() {
+                if same.len() > signatures[most_common].1.len() {
+                    most_common = j;
                 }
-                if let Some(relations) = &et.relations {
-                    let max_r = cons.tables[i].ex_relations.len();
-                    for r in relations {
-                        let mut found = false;
-                        for j in 0..max_r {
-                            if cons.tables[i].ex_relations[j].referenced_table_name == r.referenced_table_name {
-                                cons.tables[i].ex_relations[j] = r.get_clone();
-                                found = true;
-                            }
-                        }
+            }
+            let (base, same) = &signatures[most_common];
+            let base = &cons.tables[*base];
+            for i in &indexes {
+                if !same.contains(i) {
+                    warnings.push(format!("table {} matches pattern {} but its columns differ from {}; kept as a separate entity",
+                        cons.tables[*i].table, pattern.pattern, base.table));
+                }
+            }
+            if same.len() < 2 {
+                continue;
+            }
+            let members: Vec<String> = same.iter().map(|i| cons.tables[*i].table.clone()).collect();
+            for member in &members {
+                renames.insert(member.clone(), merged_name.clone());
+            }
+            merged.insert(merged_name, (members[0].clone(), members));
+        }
+        if renames.len() == 0 {
+            continue;
+        }
 
-                        if !found {
-                            cons.tables[i].ex_relations.push(r.get_clone());
+        // 代表以外のテーブルの外部キーとリレーションは代表に移す
+        let mut moved: HashMap<String, (Vec<erdh_data::ForeignKey>, Vec<erdh_data::ExRelation>)> = HashMap::new();
+        for table in cons.tables.iter_mut() {
+            if let Some(merged_name) = renames.get(&table.table) {
+                if merged[merged_name].0 != table.table {
+                    let entry = moved.entry(merged_name.clone()).or_insert_with(|| (vec![], vec![]));
+                    entry.0.append(&mut table.foreign_keys);
+                    entry.1.append(&mut table.ex_relations);
+                }
+            }
+        }
+
+        let tables = std::mem::replace(&mut cons.tables, vec![]);
+        for mut table in tables {
+            if let Some(merged_name) = renames.get(&table.table) {
+                let (representative, members) = &merged[merged_name];
+                if *representative != table.table {
+                    continue;
+                }
+                table.table = merged_name.clone();
+                table.members = members.clone();
+                if let Some((foreign_keys, ex_relations)) = moved.remove(merged_name) {
+                    for fk in foreign_keys {
+                        let exists = table.foreign_keys.iter().any(|f| f.column_name == fk.column_name
+                            && f.referenced_table_name == fk.referenced_table_name
+                            && f.referenced_column_name == fk.referenced_column_name);
+                        if !exists {
+                            table.foreign_keys.push(fk);
                         }
                     }
+                    // 参照先ごとの重複は rename_table_references で取り除く
+                    table.ex_relations.extend(ex_relations);
                 }
             }
+            cons.tables.push(table);
         }
+        rename_table_references(cons, &renames);
     }
+
+    Ok(warnings)
+}
+
+fn has_same_columns(a: &erdh_data::Table, b: &erdh_data::Table) -> bool {
+    a.columns.len() == b.columns.len()
+        && a.columns.iter().zip(b.columns.iter()).all(|(x, y)| x.name == y.name && x.column_type == y.column_type)
+}
+
+/// テーブル名の参照をまとめて置き換える
+fn rename_table_references(cons: &mut erdh_data::Construction, renames: &HashMap<String, String>) {
+    let rename = |name: &mut String| {
+        if let Some(new_name) = renames.get(name) {
+            *name = new_name.clone();
+        }
+    };
+    for table in cons.tables.iter_mut() {
+        for fk in table.foreign_keys.iter_mut() {
+            rename(&mut fk.referenced_table_name);
+        }
+        let mut ex_relations: Vec<erdh_data::ExRelation> = vec![];
+        for mut relation in std::mem::replace(&mut table.ex_relations, vec![]) {
+            rename(&mut relation.referenced_table_name);
+            if !ex_relations.iter().any(|r| r.referenced_table_name == relation.referenced_table_name) {
+                ex_relations.push(relation);
+            }
+        }
+        table.ex_relations = ex_relations;
+        if let Some(parent) = table.partition_of.as_mut() {
+            rename(parent);
+        }
+        for parent in table.inherits.iter_mut() {
+            rename(parent);
+        }
+    }
+    for trigger in cons.triggers.iter_mut() {
+        rename(&mut trigger.table);
+        for modified in trigger.modifies.iter_mut() {
+            rename(modified);
+        }
+        dedup_names(&mut trigger.modifies);
+    }
+    for routine in cons.routines.iter_mut() {
+        for referenced in routine.referenced_tables.iter_mut() {
+            rename(referenced);
+        }
+        dedup_names(&mut routine.referenced_tables);
+    }
+}
+
+/// 順序を保ったまま重複を取り除く
+fn dedup_names(names: &mut Vec<String>) {
+    let mut seen = vec![];
+    names.retain(|name| {
+        if seen.contains(name) {
+            false
+        } else {
+            seen.push(name.clone());
+            true
+        }
+    });
+}
+
+#[test]
+fn collapse_table_patterns_no_capture_error() {
+    let yaml = r#"
+db_name: test
+tables:
+  - {table: log_202101, group: DATA, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: log_202102, group: DATA, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+"#;
+    let mut cons = erdh_data::Construction::from_yaml(&yaml).unwrap();
+    let patterns = vec![TablePattern { pattern: r"^log_\d{6}$".to_string(), placeholder: None }];
+    let e = collapse_table_patterns(&mut cons, &patterns).unwrap_err();
+    assert_eq!(e.to_string(), r"^log_\d{6}$ has no capture group for the varying part");
+    assert_eq!(cons.tables.len(), 2);
+}
+
+#[test]
+fn collapse_table_patterns_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - table: log_202101
+    group: DATA
+    columns:
+      - {name: id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+    indexes: []
+    foreign_keys: []
+    ex_relations:
+      - {referenced_table_name: members, columns: [{from: member_id, to: id}], this_conn: many, that_conn: one}
+    is_master: ~
+  - table: log_202102
+    group: DATA
+    columns:
+      - {name: id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+  - table: log_202103
+    group: DATA
+    columns:
+      - {name: id, type: bigint, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+  - table: members
+    group: DATA
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations:
+      - {referenced_table_name: log_202102, columns: [{from: id, to: member_id}], this_conn: one, that_conn: many}
+    is_master: ~
+    "#;
+    let mut cons = erdh_data::Construction::from_yaml(&yaml).unwrap();
+    let patterns = vec![TablePattern {
+        pattern: r"^log_(\d{6})$".to_string(),
+        placeholder: Some("yyyymm".to_string()),
+    }];
+    let warnings = collapse_table_patterns(&mut cons, &patterns).unwrap();
+    assert_eq!(warnings.len(), 1);
+
+    let names: Vec<&str> = cons.tables.iter().map(|t| t.table.as_str()).collect();
+    assert_eq!(names, vec!["log_{yyyymm}", "log_202103", "members"]);
+    assert_eq!(cons.tables[0].members, vec!["log_202101", "log_202102"]);
+    assert_eq!(cons.tables[2].ex_relations[0].referenced_table_name, "log_{yyyymm}");
+
+    // まとめる前の名前での ex_info の指定も効く
+    let ex = ExtraConfig::from_yaml(r#"
+tables:
+  - table: log_202102
+    note: monthly log
+  - table: members
+    relations:
+      - {referenced_table_name: log_202101, columns: [{from: id, to: member_id}], this_conn: one, that_conn: zero-many}
+"#).unwrap();
+    apply_ex_info(&mut cons, &ex).unwrap();
+    assert_eq!(cons.tables[0].note, Some("monthly log".to_string()));
+    assert_eq!(cons.tables[2].ex_relations.len(), 1);
+    assert_eq!(cons.tables[2].ex_relations[0].that_conn, erdh_data::Connection::ZeroMany);
+}
+
+#[test]
+fn collapse_table_patterns_odd_first_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - table: shard_0
+    group: DATA
+    columns:
+      - {name: id, type: bigint, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+  - table: shard_1
+    group: DATA
+    columns:
+      - {name: id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+  - table: shard_2
+    group: DATA
+    columns:
+      - {name: id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+    indexes: []
+    foreign_keys:
+      - {constraint_name: fk_member, column_name: member_id, referenced_table_name: members, referenced_column_name: id}
+    ex_relations:
+      - {referenced_table_name: members, columns: [{from: member_id, to: id}], this_conn: many, that_conn: one}
+    is_master: ~
+  - table: members
+    group: DATA
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+    "#;
+    let mut cons = erdh_data::Construction::from_yaml(&yaml).unwrap();
+    let patterns = vec![TablePattern { pattern: r"^shard_(\d+)$".to_string(), placeholder: None }];
+    let warnings = collapse_table_patterns(&mut cons, &patterns).unwrap();
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert_eq!(warnings[0].starts_with("table shard_0 "), true, "{:?}", warnings);
+
+    let names: Vec<&str> = cons.tables.iter().map(|t| t.table.as_str()).collect();
+    assert_eq!(names, vec!["shard_0", "shard_{*}", "members"]);
+    // 代表以外のテーブルの外部キーとリレーションも残る
+    assert_eq!(cons.tables[1].foreign_keys.len(), 1);
+    assert_eq!(cons.tables[1].ex_relations[0].referenced_table_name, "members");
+}
+
+#[test]
+fn merge_constructions_success() {
+    let core = r#"
+db_name: core
+tables:
+  - table: orders
+    group: DATA
+    columns:
+      - {name: status, type: varchar, key: "", extra: "", default: ~, not_null: true, is_primary: false, user_type: order_status}
+    indexes: []
+    foreign_keys: []
+    ex_relations:
+      - {referenced_table_name: members, columns: [{from: member_id, to: id}], this_conn: many, that_conn: one}
+    is_master: ~
+  - table: members
+    group: DATA
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+user_types:
+  - {name: order_status, kind: enum, values: [open, closed]}
+    "#;
+    let billing = r#"
+db_name: billing
+tables:
+  - table: members
+    group: DATA
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+    "#;
+    let constructions = vec![
+        ("core".to_string(), erdh_data::Construction::from_yaml(&core).unwrap()),
+        ("billing".to_string(), erdh_data::Construction::from_yaml(&billing).unwrap()),
+    ];
+    let cons = merge_constructions(constructions).unwrap();
+    assert_eq!(cons.db_name, "core, billing");
+    let names: Vec<&str> = cons.tables.iter().map(|t| t.table.as_str()).collect();
+    assert_eq!(names, vec!["core.orders", "core.members", "billing.members"]);
+    assert_eq!(cons.tables[0].ex_relations[0].referenced_table_name, "core.members");
+    assert_eq!(cons.tables[0].columns[0].user_type, Some("core.order_status".to_string()));
+    assert_eq!(cons.user_types[0].name, "core.order_status");
+
+    let constructions = vec![
+        ("core".to_string(), erdh_data::Construction::from_yaml(&core).unwrap()),
+        ("core".to_string(), erdh_data::Construction::from_yaml(&billing).unwrap()),
+    ];
+    assert_eq!(merge_constructions(constructions).is_err(), true);
+}
+
+#[test]
+fn expand_output_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - {table: members, group: DATA, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: items, group: MASTER, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: orders, group: DATA, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+"#;
+    let cons = erdh_data::Construction::from_yaml(&yaml).unwrap();
+    let output = Output {
+        path: "out/{group}.puml".to_string(),
+        format: None,
+        group: None,
+        detail: None,
+        plantuml: None,
+    };
+    let targets = expand_output(&cons, &output);
+    assert_eq!(targets, vec![
+        ("out/DATA.puml".to_string(), Some(vec!["DATA".to_string()])),
+        ("out/MASTER.puml".to_string(), Some(vec!["MASTER".to_string()])),
+    ]);
+
+    let output = Output { path: "overview.puml".to_string(), ..output };
+    assert_eq!(expand_output(&cons, &output), vec![("overview.puml".to_string(), None)]);
+}
+
+#[test]
+fn apply_ex_info_rules_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - {table: mst_items, group: test, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: mst_items_history, group: test, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: log_access, group: test, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: mst_special, group: test, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+"#;
+    let ex_yaml = r#"
+rules:
+  - pattern: "^mst_.*"
+    group: MASTER
+    is_master: true
+  - prefix: log_
+    group: LOG
+  - suffix: _history
+    group: HISTORY
+    priority: 10
+tables:
+  - table: mst_special
+    group: DATA
+"#;
+    let mut cons = erdh_data::Construction::from_yaml(&yaml).unwrap();
+    let ex = ExtraConfig::from_yaml(&ex_yaml).unwrap();
+    apply_ex_info(&mut cons, &ex).unwrap();
+
+    let groups: Vec<&str> = cons.tables.iter().map(|t| t.group.as_str()).collect();
+    assert_eq!(groups, vec!["MASTER", "HISTORY", "LOG", "DATA"]);
+    assert_eq!(cons.tables[1].is_master, Some(true));
+    assert_eq!(cons.tables[2].is_master, None);
+    assert_eq!(cons.tables[3].is_master, Some(true));
+
+    let ex = ExtraConfig::from_yaml("rules:\n  - group: MASTER\n").unwrap();
+    assert_eq!(apply_ex_info(&mut cons, &ex).is_err(), true);
+}
+
+#[test]
+fn apply_ex_info_curation_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - table: members
+    group: DATA
+    columns:
+      - {name: id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+      - {name: name, type: text, key: "", extra: "", default: ~, not_null: true, is_primary: false}
+      - {name: password_hash, type: text, key: "", extra: "", default: ~, not_null: true, is_primary: false}
+      - {name: created_at, type: datetime, key: "", extra: "", default: ~, not_null: true, is_primary: false}
+    indexes: []
+    foreign_keys: []
+    ex_relations:
+      - {referenced_table_name: audit_logs, columns: [{from: id, to: member_id}], this_conn: one, that_conn: many}
+      - {referenced_table_name: tmp_members, columns: [{from: id, to: id}], this_conn: one, that_conn: one}
+    is_master: ~
+  - {table: tmp_members, group: DATA, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - table: orders
+    group: DATA
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations:
+      - {referenced_table_name: members, columns: [{from: member_id, to: id}], this_conn: many, that_conn: one}
+      - {referenced_table_name: tmp_members, columns: [{from: member_id, to: id}], this_conn: many, that_conn: one}
+    is_master: ~
+"#;
+    let ex_yaml = r#"
+rules:
+  - prefix: tmp_
+    hide: true
+tables:
+  - table: orders
+    remove_relations: ["*"]
+    relations:
+      - {referenced_table_name: members, columns: [{from: member_id, to: id}], this_conn: many, that_conn: zero-or-one}
+  - table: members
+    display_name: Members
+    note: registered users
+    remove_relations: [audit_logs]
+    column_order: [id, created_at]
+    columns:
+      - {name: password_hash, hide: true}
+      - {name: name, display_name: full name, note: not unique}
+"#;
+    let mut cons = erdh_data::Construction::from_yaml(&yaml).unwrap();
+    apply_ex_info(&mut cons, &ExtraConfig::from_yaml(&ex_yaml).unwrap()).unwrap();
+
+    let members = &cons.tables[0];
+    assert_eq!(members.get_display_name(), "Members");
+    assert_eq!(members.note, Some("registered users".to_string()));
+    assert_eq!(members.ex_relations.len(), 1);
+    assert_eq!(members.ex_relations[0].referenced_table_name, "tmp_members");
+    let names: Vec<&str> = members.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["id", "created_at", "name", "password_hash"]);
+    assert_eq!(members.columns[2].get_display_name(), "full name");
+    assert_eq!(members.columns[3].is_hidden, true);
+    assert_eq!(members.visible_columns(DetailLevel::Full).0.len(), 3);
+    assert_eq!(cons.tables[1].is_hidden, true);
+
+    let orders = &cons.tables[2];
+    assert_eq!(orders.ex_relations.len(), 1);
+    assert_eq!(orders.ex_relations[0].referenced_table_name, "members");
+    assert_eq!(orders.ex_relations[0].that_conn, erdh_data::Connection::ZeroOrOne);
 }
@@ -1,23 +1,37 @@
-use super::erdh_data::Construction;
-use super::erdh_data::Connection;
+use super::erdh_data::{Construction, Table, Column, ConstraintKind, KeyGenerationKind, TableStatistics};
+use super::erdh_data::{Connection, PackageStyle, GROUP_SEPARATOR, is_group_in, is_group_selected};
+use super::super::config::{PlantUmlOption, EnumValueStyle, DetailLevel};
 use std::io::{Write};
+use std::collections::HashMap;
 
-pub fn write_puml<W: Write>(cons: &Construction, writer: &mut W, groups: Option<Vec<String>>) -> Result<(), Box<std::error::Error>> {
+pub fn write_puml<W: Write>(cons: &Construction, writer: &mut W, groups: Option<Vec<String>>, option: &PlantUmlOption, detail: DetailLevel) -> Result<(), Box<std::error::Error>> {
     // let mut writer = BufWriter::new(File::create(path)?);
 
     writer.write("@startuml\n".as_bytes())?;
 
     let mut usable_tables = vec![];
 
+    // 親テーブルにまとめるパーティション (パーティション名 => 親テーブル名)
+    let collapse = option.collapse_partitions.unwrap_or(false);
+    let mut collapsed = HashMap::new();
+    if collapse {
+        for tbl in &cons.tables {
+            if let Some(parent) = &tbl.partition_of {
+                if cons.tables.iter().any(|t| t.table == *parent) {
+                    collapsed.insert(&tbl.table, parent);
+                }
+            }
+        }
+    }
+
     // グループ一覧
     let mut gorups = vec![];
     for tbl in &cons.tables {
-        if let Some(group_vec) = &groups {
-            if group_vec.len() > 0 {
-                if !group_vec.contains(&tbl.group) {
-                    continue;
-                }
-            }
+        if collapsed.contains_key(&tbl.table) || tbl.is_hidden {
+            continue;
+        }
+        if !is_group_selected(&tbl.group, groups.as_ref()) {
+            continue;
         }
         usable_tables.push(&tbl.table);
         if !gorups.contains(&&tbl.group) {
@@ -25,54 +39,28 @@ pub fn write_puml<W: Write>(cons: &Construction, writer: &mut W, groups: Option<
         }
     }
 
-    for group in gorups {
-        let iter = cons.tables.iter().filter_map(|t| if t.group == *group { Some(t) } else { None });
-        let mut cnt = 0;
-        for table in iter {
-            if cnt == 0 {
-                writer.write(format!("package \"{}\" as {} ", group, group).as_bytes())?;
-                writer.write("{\n".as_bytes())?;
-            }
-
-            writer.write("  ".as_bytes())?;
-            writer.write(format!("entity \"{}\" as {} <<D,TRANSACTION_MARK_COLOR>>", table.table, table.table).as_bytes())?;
-            writer.write(" {\n".as_bytes())?;
-
-            let mut column_cnt = 0;
-            let max_count = 3;
-            let mut absent_count = 0;
-            for column in &table.columns {
-                column_cnt += 1;
-                if column_cnt > max_count {
-                    absent_count += 1;
-                    continue;
-                }
-                writer.write("    ".as_bytes())?;
-                if column.is_primary {
-                    writer.write("+ ".as_bytes())?;
-                    writer.write(column.name.as_bytes())?;
-                    writer.write(" [PK]".as_bytes())?;
-                    writer.write("\n".as_bytes())?;
-                    
-                    writer.write("    ".as_bytes())?;
-                    writer.write("--".as_bytes())?;
-                    writer.write("\n".as_bytes())?;
-                } else {
-                    writer.write(column.name.as_bytes())?;
-                    writer.write("\n".as_bytes())?;
-                }
-            }
-            if absent_count > 0 {
-                writer.write(format!("    .. {} more ..\n", absent_count).as_bytes())?;
-            }
-            writer.write("  }\n".as_bytes())?;
-
-            cnt += 1;
-        }
-        if cnt > 0 {
-            writer.write("}\n".as_bytes())?;
+    // "Sales/Orders" のようなグループは入れ子のパッケージにする
+    let mut top_groups: Vec<&str> = vec![];
+    for group in &gorups {
+        let top = group.split(GROUP_SEPARATOR).next().unwrap_or("");
+        if !top_groups.contains(&top) {
+            top_groups.push(top);
         }
     }
+    for group in top_groups {
+        write_package(cons, writer, group, &gorups, &usable_tables, option, detail)?;
+    }
+    write_group_notes(cons, writer, &gorups)?;
+
+    // 列挙型の値一覧
+    match option.enum_values {
+        Some(EnumValueStyle::Note) => write_enum_notes(cons, writer, &usable_tables)?,
+        Some(EnumValueStyle::Entity) => write_enum_entities(cons, writer, &usable_tables)?,
+        _ => {}
+    }
+
+    // ex_info で指定したノート
+    write_notes(cons, writer, &usable_tables)?;
 
     // ここで出力されるカーディナリティも対象グループにより取捨選択する。
     for table in &cons.tables {
@@ -80,25 +68,404 @@ pub fn write_puml<W: Write>(cons: &Construction, writer: &mut W, groups: Option<
             continue;
         }
         for ex_relation in &table.ex_relations {
-            if !usable_tables.contains(&&ex_relation.referenced_table_name) {
+            let referenced_table_name = resolve_collapsed(&collapsed, &ex_relation.referenced_table_name);
+            if !usable_tables.contains(&referenced_table_name) {
                 continue;
             }
-            writer.write(table.table.as_bytes())?;
+            writer.write(to_alias(&table.table).as_bytes())?;
             writer.write("  ".as_bytes())?;
             writer.write(get_this_cardinality(&ex_relation.this_conn).as_bytes())?;
             writer.write("--".as_bytes())?;
             writer.write(get_that_cardinality(&ex_relation.that_conn).as_str().as_bytes())?;
             writer.write("  ".as_bytes())?;
-            writer.write(ex_relation.referenced_table_name.as_bytes())?;
+            writer.write(to_alias(referenced_table_name).as_bytes())?;
             writer.write("\n".as_bytes())?;
         }
     }
 
+    // パーティションと継承
+    for table in &cons.tables {
+        if !usable_tables.contains(&&table.table) {
+            continue;
+        }
+        if collapse {
+            if let Some(partition_key) = &table.partition_key {
+                writer.write(format!("note bottom of {}\n", to_alias(&table.table)).as_bytes())?;
+                writer.write(format!("  partitioned by {}\n", partition_key).as_bytes())?;
+                writer.write(format!("  {} partitions\n", table.partitions.len()).as_bytes())?;
+                if option.list_partitions.unwrap_or(false) {
+                    for partition in &table.partitions {
+                        match &partition.bound {
+                            Some(bound) => writer.write(format!("  - {} {}\n", partition.name, bound).as_bytes())?,
+                            None => writer.write(format!("  - {}\n", partition.name).as_bytes())?,
+                        };
+                    }
+                }
+                writer.write("end note\n".as_bytes())?;
+            }
+        } else if let Some(parent) = &table.partition_of {
+            if usable_tables.contains(&parent) {
+                writer.write(format!("{} <|.. {} : partition\n", to_alias(parent), to_alias(&table.table)).as_bytes())?;
+            }
+        }
+        for parent in &table.inherits {
+            if usable_tables.contains(&parent) {
+                writer.write(format!("{} <|-- {}\n", to_alias(parent), to_alias(&table.table)).as_bytes())?;
+            }
+        }
+    }
+
+    // トリガーによる書き込み先
+    if option.trigger_edges.unwrap_or(false) {
+        let mut edges = vec![];
+        for trigger in &cons.triggers {
+            if !usable_tables.contains(&&trigger.table) {
+                continue;
+            }
+            for modified in &trigger.modifies {
+                if *modified == trigger.table || !usable_tables.contains(&modified) {
+                    continue;
+                }
+                let edge = (&trigger.table, modified);
+                if !edges.contains(&edge) {
+                    edges.push(edge);
+                }
+            }
+        }
+        for (from, to) in edges {
+            writer.write(format!("{} ..> {} : writes to\n", to_alias(from), to_alias(to)).as_bytes())?;
+        }
+    }
+
     writer.write("@enduml\n".as_bytes())?;
     
     Ok(())
 }
 
+/// まとめられたパーティションの場合は最上位の親テーブル名を返す
+fn resolve_collapsed<'a>(collapsed: &HashMap<&'a String, &'a String>, table_name: &'a String) -> &'a String {
+    let mut result = table_name;
+    while let Some(parent) = collapsed.get(result) {
+        if *parent == table_name {
+            break;
+        }
+        result = parent;
+    }
+    result
+}
+
+/// 行数の桁に応じた色
+fn get_size_colour(statistics: &TableStatistics) -> Option<&'static str> {
+    match statistics.row_count {
+        Some(n) if n >= 10_000_000 => Some("#FF9999"),
+        Some(n) if n >= 1_000_000 => Some("#FFBB99"),
+        Some(n) if n >= 100_000 => Some("#FFDD99"),
+        Some(n) if n >= 10_000 => Some("#FFFFBB"),
+        _ => None,
+    }
+}
+
+/// "12,345 rows, 3.2 MB data, 1.0 MB index"
+fn format_statistics(statistics: &TableStatistics) -> String {
+    let mut parts = vec![];
+    if let Some(row_count) = statistics.row_count {
+        parts.push(format!("{} rows", format_count(row_count)));
+    }
+    if let Some(data_size) = statistics.data_size {
+        parts.push(format!("{} data", format_bytes(data_size)));
+    }
+    if let Some(index_size) = statistics.index_size {
+        parts.push(format!("{} index", format_bytes(index_size)));
+    }
+    parts.join(", ")
+}
+
+fn format_count(n: i64) -> String {
+    let digits = n.abs().to_string();
+    let mut result = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            result.push(',');
+        }
+        result.push(c);
+    }
+    if n < 0 {
+        result.insert(0, '-');
+    }
+    result
+}
+
+fn format_bytes(n: i64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = n as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", n, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// 列名の後ろに付けるマーカー
+fn get_column_markers(table: &Table, column: &Column, option: &PlantUmlOption) -> String {
+    let mut markers = String::new();
+    if option.mark_unique.unwrap_or(false) {
+        let is_unique = table.constraints.iter()
+            .any(|c| c.kind == ConstraintKind::Unique && c.columns.contains(&column.name));
+        if is_unique {
+            markers.push_str(" [UQ]");
+        }
+    }
+    if option.mark_generated.unwrap_or(false) {
+        if let Some(key_generation) = &column.key_generation {
+            markers.push_str(match key_generation.kind {
+                KeyGenerationKind::AutoIncrement => " [AI]",
+                KeyGenerationKind::IdentityAlways | KeyGenerationKind::IdentityByDefault => " [IDENTITY]",
+                KeyGenerationKind::Sequence => " [SEQ]",
+            });
+        }
+        if column.computed.is_some() {
+            markers.push_str(" [GEN]");
+        }
+    }
+
+    markers
+}
+
+fn write_entity<W: Write>(cons: &Construction, table: &Table, writer: &mut W, option: &PlantUmlOption, detail: DetailLevel) -> Result<(), Box<std::error::Error>> {
+    writer.write("  ".as_bytes())?;
+    // 読み込み対象外のテーブルは名前だけを出力する
+    if table.is_stub {
+        writer.write(format!("entity \"{}\" as {} <<stub>> #white {{\n  }}\n", table.get_display_name(), to_alias(&table.table)).as_bytes())?;
+        return Ok(());
+    }
+    let label = if table.members.len() > 0 {
+        format!("{} ({} tables)", table.get_display_name(), table.members.len())
+    } else {
+        table.get_display_name().to_string()
+    };
+    writer.write(format!("entity \"{}\" as {} <<D,TRANSACTION_MARK_COLOR>>", label, to_alias(&table.table)).as_bytes())?;
+    if option.colour_by_size.unwrap_or(false) {
+        if let Some(colour) = table.statistics.as_ref().and_then(|s| get_size_colour(s)) {
+            writer.write(format!(" {}", colour).as_bytes())?;
+        }
+    }
+    writer.write(" {\n".as_bytes())?;
+
+    let (columns, absent_count) = table.visible_columns(detail);
+    for column in columns {
+        writer.write("    ".as_bytes())?;
+        let markers = get_column_markers(table, column, option);
+        if column.is_primary {
+            writer.write("+ ".as_bytes())?;
+            writer.write(column.get_display_name().as_bytes())?;
+            writer.write(" [PK]".as_bytes())?;
+            writer.write(markers.as_bytes())?;
+            writer.write("\n".as_bytes())?;
+            
+            writer.write("    ".as_bytes())?;
+            writer.write("--".as_bytes())?;
+            writer.write("\n".as_bytes())?;
+        } else {
+            writer.write(column.get_display_name().as_bytes())?;
+            writer.write(markers.as_bytes())?;
+            writer.write("\n".as_bytes())?;
+        }
+    }
+    if absent_count > 0 {
+        writer.write(format!("    .. {} more ..\n", absent_count).as_bytes())?;
+    }
+    if option.show_statistics.unwrap_or(false) {
+        if let Some(statistics) = &table.statistics {
+            writer.write(format!("    .. {} ..\n", format_statistics(statistics)).as_bytes())?;
+        }
+    }
+    if option.show_checks.unwrap_or(false) {
+        let checks: Vec<_> = table.constraints.iter().filter(|c| c.kind == ConstraintKind::Check).collect();
+        if checks.len() > 0 {
+            writer.write("    .. check ..\n".as_bytes())?;
+            for check in checks {
+                let expression = check.expression.clone().unwrap_or("".to_string());
+                if check.name.is_empty() {
+                    writer.write(format!("    {}\n", expression).as_bytes())?;
+                } else {
+                    writer.write(format!("    {}: {}\n", check.name, expression).as_bytes())?;
+                }
+            }
+        }
+    }
+    if option.show_triggers.unwrap_or(false) {
+        let triggers: Vec<_> = cons.triggers.iter().filter(|t| t.table == table.table).collect();
+        if triggers.len() > 0 {
+            writer.write("    .. trigger ..\n".as_bytes())?;
+            for trigger in triggers {
+                writer.write(format!("    {}: {} {}\n", trigger.name, trigger.timing, trigger.event).as_bytes())?;
+            }
+        }
+    }
+    writer.write("  }\n".as_bytes())?;
+
+    Ok(())
+}
+
+/// グループ path のパッケージを、その下のグループのパッケージを含めて出力する
+/// 絞り込みで選ばれたグループの親のパッケージも開くので、テーブルは usable_tables にあるものだけを出力する
+fn write_package<W: Write>(cons: &Construction, writer: &mut W, path: &str, groups: &Vec<&String>, usable_tables: &Vec<&String>, option: &PlantUmlOption, detail: DetailLevel) -> Result<(), Box<std::error::Error>> {
+    let group = cons.find_group(path);
+    let label = group.and_then(|g| g.display_name.as_ref().map(|n| n.as_str()))
+        .unwrap_or(path.rsplit(GROUP_SEPARATOR).next().unwrap_or(path));
+    let style = group.and_then(|g| g.style).unwrap_or(PackageStyle::Package);
+    writer.write(format!("{} \"{}\" as {} ", style.keyword(), label, to_alias(path)).as_bytes())?;
+    if let Some(color) = group.and_then(|g| g.color.as_ref()) {
+        writer.write(format!("{} ", to_colour(color)).as_bytes())?;
+    }
+    writer.write("{\n".as_bytes())?;
+
+    for table in cons.tables.iter().filter(|t| t.group == path && usable_tables.contains(&&t.table)) {
+        write_entity(cons, table, writer, option, detail)?;
+    }
+
+    let mut children: Vec<String> = vec![];
+    for group in groups {
+        if !is_group_in(group, path) || group.as_str() == path {
+            continue;
+        }
+        let rest = &group[path.len() + 1..];
+        let child = format!("{}{}{}", path, GROUP_SEPARATOR, rest.split(GROUP_SEPARATOR).next().unwrap_or(rest));
+        if !children.contains(&child) {
+            children.push(child);
+        }
+    }
+    for child in children {
+        write_package(cons, writer, &child, groups, usable_tables, option, detail)?;
+    }
+
+    writer.write("}\n".as_bytes())?;
+    Ok(())
+}
+
+/// ex_info で指定したグループの説明をパッケージのノートにする
+fn write_group_notes<W: Write>(cons: &Construction, writer: &mut W, groups: &Vec<&String>) -> Result<(), Box<std::error::Error>> {
+    for group in &cons.groups {
+        let description = match &group.description {
+            Some(description) => description,
+            None => continue,
+        };
+        if !groups.iter().any(|g| is_group_in(g, &group.name)) {
+            continue;
+        }
+        writer.write(format!("note top of {}\n", to_alias(&group.name)).as_bytes())?;
+        for line in description.lines() {
+            writer.write(format!("  {}\n", line).as_bytes())?;
+        }
+        writer.write("end note\n".as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// "LightBlue" も "#LightBlue" も受け付ける
+fn to_colour(colour: &str) -> String {
+    if colour.starts_with('#') { colour.to_string() } else { format!("#{}", colour) }
+}
+
+fn write_notes<W: Write>(cons: &Construction, writer: &mut W, usable_tables: &Vec<&String>) -> Result<(), Box<std::error::Error>> {
+    for table in &cons.tables {
+        if !usable_tables.contains(&&table.table) {
+            continue;
+        }
+        let mut lines = vec![];
+        if let Some(note) = &table.note {
+            lines.push(format!("  {}\n", note));
+        }
+        for column in table.columns.iter().filter(|c| !c.is_hidden) {
+            if let Some(note) = &column.note {
+                lines.push(format!("  {}: {}\n", column.get_display_name(), note));
+            }
+        }
+        if lines.len() == 0 {
+            continue;
+        }
+        writer.write(format!("note left of {}\n", to_alias(&table.table)).as_bytes())?;
+        for line in lines {
+            writer.write(line.as_bytes())?;
+        }
+        writer.write("end note\n".as_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn write_enum_notes<W: Write>(cons: &Construction, writer: &mut W, usable_tables: &Vec<&String>) -> Result<(), Box<std::error::Error>> {
+    for table in &cons.tables {
+        if !usable_tables.contains(&&table.table) {
+            continue;
+        }
+        let mut lines = vec![];
+        for column in &table.columns {
+            if let Some(user_type) = column.user_type.as_ref().and_then(|n| cons.find_user_type(n)) {
+                if user_type.values.len() > 0 {
+                    lines.push(format!("  {}: {}\n", column.name, user_type.values.join(" | ")));
+                }
+            }
+        }
+        if lines.len() == 0 {
+            continue;
+        }
+        writer.write(format!("note right of {}\n", to_alias(&table.table)).as_bytes())?;
+        for line in lines {
+            writer.write(line.as_bytes())?;
+        }
+        writer.write("end note\n".as_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn write_enum_entities<W: Write>(cons: &Construction, writer: &mut W, usable_tables: &Vec<&String>) -> Result<(), Box<std::error::Error>> {
+    let mut written = vec![];
+    let mut links = vec![];
+    for table in &cons.tables {
+        if !usable_tables.contains(&&table.table) {
+            continue;
+        }
+        for column in &table.columns {
+            let user_type = match column.user_type.as_ref().and_then(|n| cons.find_user_type(n)) {
+                Some(t) if t.values.len() > 0 => t,
+                _ => continue,
+            };
+            let alias = to_enum_alias(&user_type.name);
+            if !written.contains(&alias) {
+                writer.write(format!("enum \"{}\" as {} {{\n", user_type.name, alias).as_bytes())?;
+                for value in &user_type.values {
+                    writer.write(format!("  {}\n", value).as_bytes())?;
+                }
+                writer.write("}\n".as_bytes())?;
+                written.push(alias.clone());
+            }
+            links.push(format!("{} ..> {} : {}\n", to_alias(&table.table), alias, column.name));
+        }
+    }
+    for link in links {
+        writer.write(link.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// 名前をPlantUMLのエイリアスとして使える文字列に変換する
+fn to_alias(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+fn to_enum_alias(name: &str) -> String {
+    format!("enum_{}", to_alias(name))
+}
+
 fn get_this_cardinality(conn: &Connection) -> String {
     let result = match conn {
         Connection::One => "--",
@@ -124,3 +491,65 @@ fn get_that_cardinality(conn: &Connection) -> String {
 
     result.to_string()
 }
+
+#[test]
+fn format_statistics_success() {
+    let statistics = TableStatistics {
+        row_count: Some(1234567),
+        data_size: Some(3 * 1024 * 1024 + 200 * 1024),
+        index_size: Some(512),
+        last_analyzed: None,
+    };
+    assert_eq!(format_statistics(&statistics), "1,234,567 rows, 3.2 MB data, 512 B index");
+    assert_eq!(get_size_colour(&statistics), Some("#FFBB99"));
+}
+
+#[test]
+fn write_puml_nested_groups_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - {table: orders, group: Sales/Orders, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: invoices, group: Sales/Invoices, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: members, group: Members, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+groups:
+  - {name: Sales, display_name: Sales domain, color: LightBlue, style: frame, description: ~}
+  - {name: Sales/Orders, display_name: ~, color: ~, style: ~, description: Order handling}
+"#;
+    let cons = Construction::from_yaml(yaml).unwrap();
+    let mut out = vec![];
+    write_puml(&cons, &mut out, Some(vec!["Sales".to_string()]), &PlantUmlOption::default(), DetailLevel::Full).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = r#"frame "Sales domain" as Sales #LightBlue {
+package "Orders" as Sales_Orders {
+  entity "orders" as orders <<D,TRANSACTION_MARK_COLOR>> {
+  }
+}
+package "Invoices" as Sales_Invoices {
+  entity "invoices" as invoices <<D,TRANSACTION_MARK_COLOR>> {
+  }
+}
+}
+note top of Sales_Orders
+  Order handling
+end note
+"#;
+    assert_eq!(out.contains(expected), true, "{}", out);
+    assert_eq!(out.contains("members"), false, "{}", out);
+}
+
+#[test]
+fn write_puml_parent_group_filtered_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - {table: orders, group: Sales/Orders, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: sales_reports, group: Sales, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+"#;
+    let cons = Construction::from_yaml(yaml).unwrap();
+    let mut out = vec![];
+    write_puml(&cons, &mut out, Some(vec!["Sales/Orders".to_string()]), &PlantUmlOption::default(), DetailLevel::Full).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.contains("entity \"orders\""), true, "{}", out);
+    assert_eq!(out.contains("sales_reports"), false, "{}", out);
+}
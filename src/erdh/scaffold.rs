@@ -0,0 +1,311 @@
+//! 読み込んだ構造から ex_info の雛形を作る
+use super::erdh_data::{Construction, ConstraintKind, Connection, ExRelation, ExRelationColumn, ForeignKey, Table};
+use super::super::config::extra_config::{self, ExtraConfig};
+use super::super::file_format::{self, FileFormat};
+use std::collections::HashMap;
+use std::path::Path;
+extern crate failure;
+extern crate serde_yaml;
+
+/// すべてのテーブルについて、現在のグループ、is_master の推測、外部キーから作ったリレーションを並べる
+pub fn scaffold_ex_info(cons: &Construction) -> ExtraConfig {
+    let tables = cons.tables.iter()
+        .filter(|t| !t.is_stub)
+        .map(|t| extra_config::Table {
+            table: t.table.clone(),
+            is_master: if guess_master(cons, t) { Some(true) } else { None },
+            group: Some(t.group.clone()),
+            relations: {
+                let relations = relations_from_foreign_keys(t);
+                if relations.len() > 0 { Some(relations) } else { None }
+            },
+            hide: None,
+            remove_relations: None,
+            display_name: None,
+            column_order: None,
+            note: None,
+            columns: None,
+        })
+        .collect();
+    ExtraConfig { rules: vec![], tables: tables, groups: vec![] }
+}
+
+/// 既存の ex_info にまだ載っていないテーブルだけを返す (既存の指定は書き換えない)
+/// rules でグループや is_master が決まるテーブルには、その項目を書かない
+pub fn scaffold_additions(existing: &ExtraConfig, scaffold: ExtraConfig) -> Result<Vec<extra_config::Table>, failure::Error> {
+    // (group を決めるか, is_master を決めるか, 名前の条件)
+    let mut rules = vec![];
+    for rule in &existing.rules {
+        rules.push((rule.group.is_some(), rule.is_master.is_some(), rule.matchers()?));
+    }
+    let mut added = vec![];
+    for mut table in scaffold.tables {
+        if existing.tables.iter().any(|t| t.table == table.table) {
+            continue;
+        }
+        for (sets_group, sets_master, matchers) in &rules {
+            if !matchers.iter().all(|re| re.is_match(&table.table)) {
+                continue;
+            }
+            if *sets_group {
+                table.group = None;
+            }
+            if *sets_master {
+                table.is_master = None;
+            }
+        }
+        added.push(table);
+    }
+    Ok(added)
+}
+
+/// 雛形を path に書き出し、書き出したファイルと追加したテーブル名を返す
+/// path が既にあれば新しいテーブルだけを追記し、コメントや既存の書き方はそのまま残す
+/// YAML 以外やフロー形式の tables には追記できないので、追加分を別の YAML ファイル (拡張子を .scaffold.yaml にしたもの) に書く
+pub fn write_scaffold(cons: &Construction, path: &str) -> Result<(String, Vec<String>), failure::Error> {
+    if Path::new(path).is_dir() {
+        return Err(failure::format_err!("{} is a directory: specify a file", path));
+    }
+    let scaffold = scaffold_ex_info(cons);
+    if !Path::new(path).exists() {
+        let added = scaffold.tables.iter().map(|t| t.table.clone()).collect();
+        let format = FileFormat::from_path(path).unwrap_or(FileFormat::Yaml);
+        std::fs::write(path, format.to_string(&without_nulls(&scaffold)?)?)?;
+        return Ok((path.to_string(), added));
+    }
+
+    let (contents, format) = file_format::read_file(path)?;
+    let existing = ExtraConfig::from_contents(&contents, format)?;
+    let tables = scaffold_additions(&existing, scaffold)?;
+    let added: Vec<String> = tables.iter().map(|t| t.table.clone()).collect();
+    if tables.len() == 0 {
+        return Ok((path.to_string(), added));
+    }
+    let appended = match format {
+        FileFormat::Yaml => append_tables(&contents, &tables)?,
+        _ => None,
+    };
+    match appended {
+        Some(appended) => {
+            std::fs::write(path, appended)?;
+            Ok((path.to_string(), added))
+        }
+        None => {
+            let separate = Path::new(path).with_extension("scaffold.yaml").to_string_lossy().to_string();
+            let ex_info = ExtraConfig { rules: vec![], tables: tables, groups: vec![] };
+            std::fs::write(&separate, FileFormat::Yaml.to_string(&without_nulls(&ex_info)?)?)?;
+            Ok((separate, added))
+        }
+    }
+}
+
+/// YAML の tables の最後の要素の後ろに tables を追記する。tables がフロー形式で書かれていれば None
+fn append_tables(contents: &str, tables: &Vec<extra_config::Table>) -> Result<Option<String>, failure::Error> {
+    let mut lines: Vec<String> = contents.lines().map(|l| l.to_string()).collect();
+    let start = match lines.iter().position(|l| l.starts_with("tables:")) {
+        Some(start) => start,
+        None => {
+            // tables が無ければ末尾に足す
+            lines.push("tables:".to_string());
+            lines.extend(to_yaml_lines(tables, "  ")?);
+            return Ok(Some(lines.join("\n") + "\n"));
+        }
+    };
+    let value = lines[start]["tables:".len()..].split(" #").next().unwrap_or("").trim().to_string();
+    match value.as_str() {
+        "" => {}
+        "[]" => lines[start] = lines[start].replacen("[]", "", 1).trim_end().to_string(),
+        _ => return Ok(None),
+    }
+    // 次のトップレベルのキーまでが tables の要素
+    let end = lines[start + 1..].iter()
+        .position(|l| l.chars().next().map_or(false, |c| !" \t-#".contains(c)))
+        .map_or(lines.len(), |i| start + 1 + i);
+    let insert_at = lines[start + 1..end].iter()
+        .rposition(|l| l.trim().len() > 0 && !l.starts_with('#'))
+        .map_or(start + 1, |i| start + 2 + i);
+    let indent = lines[start + 1..end].iter()
+        .find(|l| l.trim_start().starts_with("- "))
+        .map(|l| l[..l.len() - l.trim_start().len()].to_string())
+        .unwrap_or("  ".to_string());
+    let added = to_yaml_lines(tables, &indent)?;
+    lines.splice(insert_at..insert_at, added);
+    Ok(Some(lines.join("\n") + "\n"))
+}
+
+/// tables を YAML のリストにして、各行を indent だけ字下げする
+fn to_yaml_lines(tables: &Vec<extra_config::Table>, indent: &str) -> Result<Vec<String>, failure::Error> {
+    let s = serde_yaml::to_string(&without_nulls(tables)?)?;
+    Ok(s.lines().filter(|l| *l != "---").map(|l| format!("{}{}", indent, l)).collect())
+}
+
+/// 未指定の項目を省いた値にする
+fn without_nulls<T: serde::Serialize>(value: &T) -> Result<serde_yaml::Value, failure::Error> {
+    let mut value = serde_yaml::to_value(value)?;
+    remove_nulls(&mut value);
+    Ok(value)
+}
+
+/// 他のテーブルから参照されていて、自身は他を参照していないテーブルをマスタとみなす
+fn guess_master(cons: &Construction, table: &Table) -> bool {
+    table.foreign_keys.len() == 0
+        && cons.tables.iter().any(|t| t.foreign_keys.iter().any(|fk| fk.referenced_table_name == table.table))
+}
+
+/// 外部キー制約ごとにリレーションを作る
+/// 参照元の列が主キーかユニーク制約そのものなら1対1、参照元の列が null を許すなら参照先は0か1
+fn relations_from_foreign_keys(table: &Table) -> Vec<ExRelation> {
+    let mut names: Vec<&String> = vec![];
+    let mut groups: HashMap<&String, Vec<&ForeignKey>> = HashMap::new();
+    for fk in &table.foreign_keys {
+        if !groups.contains_key(&fk.constraint_name) {
+            names.push(&fk.constraint_name);
+        }
+        groups.entry(&fk.constraint_name).or_insert(vec![]).push(fk);
+    }
+
+    let mut primary: Vec<&String> = table.columns.iter().filter(|c| c.is_primary).map(|c| &c.name).collect();
+    primary.sort();
+    names.iter().map(|name| {
+        let fks = &groups[name];
+        let mut from: Vec<&String> = fks.iter().map(|fk| &fk.column_name).collect();
+        from.sort();
+        let is_unique = from == primary || table.constraints.iter()
+            .filter(|c| c.kind == ConstraintKind::Unique)
+            .any(|c| {
+                let mut columns: Vec<&String> = c.columns.iter().collect();
+                columns.sort();
+                columns == from
+            });
+        let nullable = fks.iter().any(|fk| table.columns.iter().any(|c| c.name == fk.column_name && !c.not_null));
+        ExRelation {
+            referenced_table_name: fks[0].referenced_table_name.clone(),
+            columns: fks.iter().map(|fk| ExRelationColumn {
+                from: fk.column_name.clone(),
+                to: fk.referenced_column_name.clone(),
+            }).collect(),
+            this_conn: if is_unique { Connection::ZeroOrOne } else { Connection::Many },
+            that_conn: if nullable { Connection::ZeroOrOne } else { Connection::One },
+        }
+    }).collect()
+}
+
+/// 未指定の項目を省く
+fn remove_nulls(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::Mapping(map) => {
+            let keys: Vec<serde_yaml::Value> = map.iter().filter(|(_, v)| v.is_null()).map(|(k, _)| k.clone()).collect();
+            for key in keys {
+                map.remove(&key);
+            }
+            for (_, v) in map.iter_mut() {
+                remove_nulls(v);
+            }
+        }
+        serde_yaml::Value::Sequence(seq) => {
+            for v in seq.iter_mut() {
+                remove_nulls(v);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[test]
+fn scaffold_ex_info_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - table: orders
+    group: DATA
+    columns:
+      - {name: id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: true}
+      - {name: member_id, type: int, key: "", extra: "", default: ~, not_null: true, is_primary: false}
+      - {name: coupon_id, type: int, key: "", extra: "", default: ~, not_null: false, is_primary: false}
+    indexes: []
+    foreign_keys:
+      - {constraint_name: fk_member, column_name: member_id, referenced_table_name: members, referenced_column_name: id}
+      - {constraint_name: fk_coupon, column_name: coupon_id, referenced_table_name: mst_coupons, referenced_column_name: id}
+    ex_relations: []
+    is_master: ~
+    constraints:
+      - {kind: unique, name: uq_coupon, columns: [coupon_id], expression: ~}
+  - table: members
+    group: DATA
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+  - table: mst_coupons
+    group: MASTER
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+"#;
+    let cons = Construction::from_yaml(yaml).unwrap();
+    let scaffold = scaffold_ex_info(&cons);
+    assert_eq!(scaffold.tables.len(), 3);
+    assert_eq!(scaffold.tables[0].is_master, None);
+    assert_eq!(scaffold.tables[1].is_master, Some(true));
+    let relations = scaffold.tables[0].relations.as_ref().unwrap();
+    assert_eq!(relations[0].referenced_table_name, "members");
+    assert_eq!((relations[0].this_conn, relations[0].that_conn), (Connection::Many, Connection::One));
+    assert_eq!(relations[1].referenced_table_name, "mst_coupons");
+    assert_eq!((relations[1].this_conn, relations[1].that_conn), (Connection::ZeroOrOne, Connection::ZeroOrOne));
+
+    let existing = ExtraConfig::from_yaml(r#"
+rules:
+  - prefix: mst_
+    group: MASTER
+tables:
+  - table: orders
+    group: SALES
+    note: hand written
+"#).unwrap();
+    let added = scaffold_additions(&existing, scaffold).unwrap();
+    let names: Vec<&str> = added.iter().map(|t| t.table.as_str()).collect();
+    assert_eq!(names, vec!["members", "mst_coupons"]);
+    assert_eq!(added[1].group, None);
+    assert_eq!(added[1].is_master, Some(true));
+}
+
+#[test]
+fn write_scaffold_keeps_existing_text_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - {table: members, group: DATA, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+  - {table: orders, group: DATA, columns: [], indexes: [], foreign_keys: [], ex_relations: [], is_master: ~}
+"#;
+    let existing = r#"# 手で書いた設定
+rules:
+  - {prefix: mst_, group: MASTER}
+tables:
+  # 会員
+  - table: members
+    note: 'hand written'  # 残す
+groups:
+  - name: DATA
+"#;
+    let cons = Construction::from_yaml(yaml).unwrap();
+    let path = std::env::temp_dir().join(format!("erdh_scaffold_{}.yaml", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+    std::fs::write(&path, existing).unwrap();
+    let (written, added) = write_scaffold(&cons, &path).unwrap();
+    assert_eq!((written.as_str(), added), (path.as_str(), vec!["orders".to_string()]));
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(contents.starts_with(existing.split("groups:").next().unwrap()), true, "{}", contents);
+    assert_eq!(contents.ends_with("groups:\n  - name: DATA\n"), true, "{}", contents);
+    let ex = ExtraConfig::from_yaml(&contents).unwrap();
+    assert_eq!(ex.tables[1].table, "orders");
+    assert_eq!(ex.groups.len(), 1);
+
+    // もう一度実行しても変わらない
+    let (_, added) = write_scaffold(&cons, &path).unwrap();
+    assert_eq!(added.len(), 0);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+    std::fs::remove_file(&path).ok();
+}
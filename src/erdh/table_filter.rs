@@ -0,0 +1,182 @@
+use super::erdh_data::{Construction, Table};
+use super::super::config::{TableFilter, ExcludedRelations};
+use regex::Regex;
+extern crate failure;
+
+/// TableFilter の名前をコンパイルしたもの
+#[derive(Debug, Default)]
+pub struct TableMatcher {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl TableMatcher {
+    pub fn new(filter: Option<&TableFilter>) -> Result<TableMatcher, failure::Error> {
+        let filter = match filter {
+            Some(filter) => filter,
+            None => return Ok(TableMatcher::default()),
+        };
+        Ok(TableMatcher {
+            include: compile_patterns(&filter.include)?,
+            exclude: compile_patterns(&filter.exclude)?,
+        })
+    }
+
+    /// 読み込み対象のテーブルなら true
+    pub fn is_match(&self, table_name: &str) -> bool {
+        if self.exclude.iter().any(|re| re.is_match(table_name)) {
+            return false;
+        }
+        self.include.len() == 0 || self.include.iter().any(|re| re.is_match(table_name))
+    }
+}
+
+fn compile_patterns(patterns: &Option<Vec<String>>) -> Result<Vec<Regex>, failure::Error> {
+    let mut result = vec![];
+    for pattern in patterns.iter().flatten() {
+        let re = if pattern.starts_with("re:") {
+            Regex::new(&pattern[3..])?
+        } else {
+            Regex::new(&glob_to_regex(pattern))?
+        };
+        result.push(re);
+    }
+
+    Ok(result)
+}
+
+/// "tmp_*" のような glob を全体一致の正規表現にする
+fn glob_to_regex(glob: &str) -> String {
+    let mut result = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => result.push_str(".*"),
+            '?' => result.push('.'),
+            _ => result.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    result.push('$');
+    result
+}
+
+/// 対象外のテーブルを取り除き、対象外のテーブルへのリレーションを削除するか名前だけのテーブルに向ける
+/// データベースから読み込んだ場合は対象外のテーブルは既に読まれていないので、リレーションだけが対象になる
+pub fn apply_table_filter(cons: &mut Construction, matcher: &TableMatcher, excluded_relations: ExcludedRelations) {
+    cons.tables.retain(|t| t.is_stub || matcher.is_match(&t.table));
+    cons.triggers.retain(|t| matcher.is_match(&t.table));
+    for trigger in cons.triggers.iter_mut() {
+        trigger.modifies.retain(|t| matcher.is_match(t));
+    }
+    for routine in cons.routines.iter_mut() {
+        routine.referenced_tables.retain(|t| matcher.is_match(t));
+    }
+
+    let mut stubs: Vec<(String, String)> = vec![];
+    for table in cons.tables.iter_mut() {
+        match excluded_relations {
+            ExcludedRelations::Drop => {
+                table.ex_relations.retain(|r| matcher.is_match(&r.referenced_table_name));
+            }
+            ExcludedRelations::Stub => {
+                for relation in &table.ex_relations {
+                    let name = &relation.referenced_table_name;
+                    if !matcher.is_match(name) && !stubs.iter().any(|(n, _)| n == name) {
+                        stubs.push((name.clone(), table.group.clone()));
+                    }
+                }
+            }
+        }
+    }
+    for (name, group) in stubs {
+        if cons.tables.iter().any(|t| t.table == name) {
+            continue;
+        }
+        // グループは参照元のテーブルに合わせる
+        cons.tables.push(Table {
+            table: name,
+            group: group,
+            columns: vec![],
+            indexes: vec![],
+            foreign_keys: vec![],
+            ex_relations: vec![],
+            is_master: None,
+            constraints: vec![],
+            partition_key: None,
+            partitions: vec![],
+            partition_of: None,
+            inherits: vec![],
+            members: vec![],
+            statistics: None,
+            is_stub: true,
+            display_name: None,
+            note: None,
+            is_hidden: false,
+        });
+    }
+}
+
+#[test]
+fn table_matcher_success() {
+    let filter = TableFilter {
+        include: None,
+        exclude: Some(vec!["schema_migrations".to_string(), "tmp_*".to_string(), "re:^bk_\\d+$".to_string()]),
+        excluded_relations: None,
+    };
+    let matcher = TableMatcher::new(Some(&filter)).unwrap();
+    assert_eq!(matcher.is_match("members"), true);
+    assert_eq!(matcher.is_match("schema_migrations"), false);
+    assert_eq!(matcher.is_match("tmp_members"), false);
+    assert_eq!(matcher.is_match("bk_01"), false);
+    assert_eq!(matcher.is_match("bk_members"), true);
+
+    let filter = TableFilter {
+        include: Some(vec!["mst_*".to_string()]),
+        exclude: Some(vec!["mst_old_*".to_string()]),
+        excluded_relations: None,
+    };
+    let matcher = TableMatcher::new(Some(&filter)).unwrap();
+    assert_eq!(matcher.is_match("mst_items"), true);
+    assert_eq!(matcher.is_match("mst_old_items"), false);
+    assert_eq!(matcher.is_match("items"), false);
+}
+
+#[test]
+fn apply_table_filter_success() {
+    let yaml = r#"
+db_name: test
+tables:
+  - table: members
+    group: DATA
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations:
+      - {referenced_table_name: tmp_items, columns: [{from: item_id, to: id}], this_conn: many, that_conn: one}
+    is_master: ~
+  - table: tmp_items
+    group: DATA
+    columns: []
+    indexes: []
+    foreign_keys: []
+    ex_relations: []
+    is_master: ~
+"#;
+    let filter = TableFilter {
+        include: None,
+        exclude: Some(vec!["tmp_*".to_string()]),
+        excluded_relations: None,
+    };
+    let matcher = TableMatcher::new(Some(&filter)).unwrap();
+
+    let mut cons = Construction::from_yaml(yaml).unwrap();
+    apply_table_filter(&mut cons, &matcher, ExcludedRelations::Drop);
+    assert_eq!(cons.tables.len(), 1);
+    assert_eq!(cons.tables[0].ex_relations.len(), 0);
+
+    let mut cons = Construction::from_yaml(yaml).unwrap();
+    apply_table_filter(&mut cons, &matcher, ExcludedRelations::Stub);
+    assert_eq!(cons.tables.len(), 2);
+    assert_eq!(cons.tables[0].ex_relations.len(), 1);
+    assert_eq!(cons.tables[1].table, "tmp_items");
+    assert_eq!(cons.tables[1].is_stub, true);
+}
@@ -0,0 +1,142 @@
+use serde::de::DeserializeOwned;
+use serde_derive::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use regex::Regex;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
+extern crate failure;
+
+/// 設定ファイル・中間形式ファイルの形式
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Copy, Clone)]
+pub enum FileFormat {
+    #[serde(rename = "yaml")]
+    Yaml,
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "toml")]
+    Toml,
+}
+
+impl FileFormat {
+    /// 拡張子から形式を決める
+    pub fn from_path(path: &str) -> Option<FileFormat> {
+        let ext = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "yaml" | "yml" => Some(FileFormat::Yaml),
+            "json" => Some(FileFormat::Json),
+            "toml" => Some(FileFormat::Toml),
+            _ => None,
+        }
+    }
+
+    /// 内容から形式を推測する
+    /// "{" で始まれば JSON、"[section]" か "key = value" で始まれば TOML、それ以外は YAML
+    pub fn detect(contents: &str) -> FileFormat {
+        let line = contents.lines()
+            .map(|l| l.trim())
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .unwrap_or("");
+        if line.starts_with('{') {
+            return FileFormat::Json;
+        }
+        let toml_line = Regex::new(r#"^(\[\[?[A-Za-z0-9_.\-" ]+\]\]?|[A-Za-z0-9_\-"]+\s*=)"#).unwrap();
+        if toml_line.is_match(line) {
+            return FileFormat::Toml;
+        }
+        FileFormat::Yaml
+    }
+
+    pub fn parse<T: DeserializeOwned>(self, contents: &str) -> Result<T, failure::Error> {
+        let result = match self {
+            FileFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+            FileFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+            FileFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+        };
+        result.map_err(|message| failure::format_err!("{}", add_suggestion(&message)))
+    }
+
+    pub fn to_string<T: serde::Serialize>(self, value: &T) -> Result<String, failure::Error> {
+        let result = match self {
+            FileFormat::Yaml => serde_yaml::to_string(value)?,
+            FileFormat::Json => serde_json::to_string_pretty(value)?,
+            // 値の後にテーブルが来るように並べ替えるため、一度 toml::Value にする
+            FileFormat::Toml => toml::to_string_pretty(&toml::Value::try_from(value)?)?,
+        };
+        Ok(result)
+    }
+}
+
+/// ファイルを読み、拡張子 (無ければ内容) から形式を決める
+pub fn read_file(path: &str) -> Result<(String, FileFormat), failure::Error> {
+    let mut file = File::open(path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let format = FileFormat::from_path(path).unwrap_or(FileFormat::detect(&contents));
+    Ok((contents, format))
+}
+
+/// "unknown field `ex-info`, expected one of ..." のようなエラーに、候補のうち最も近い名前を添える
+pub fn add_suggestion(message: &str) -> String {
+    let re = Regex::new(r"unknown (?:field|variant) `([^`]*)`, expected (?:one of )?(.*?)( at line \d+ column \d+)?$").unwrap();
+    let caps = match re.captures(message) {
+        Some(caps) => caps,
+        None => return message.to_string(),
+    };
+    let unknown = &caps[1];
+    let candidate_re = Regex::new(r"`([^`]*)`").unwrap();
+    let suggestion = candidate_re.captures_iter(&caps[2])
+        .map(|c| c[1].to_string())
+        .map(|candidate| (edit_distance(unknown, &candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= std::cmp::max(2, candidate.len() / 3))
+        .min_by_key(|(distance, _)| *distance);
+    match suggestion {
+        Some((_, candidate)) => {
+            let location = caps.get(3).map_or("", |m| m.as_str());
+            format!("{} (did you mean `{}`?){}", &message[..message.len() - location.len()], candidate, location)
+        }
+        None => message.to_string(),
+    }
+}
+
+/// レーベンシュタイン距離
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current.push(*[previous[j] + cost, previous[j + 1] + 1, current[j] + 1].iter().min().unwrap());
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[test]
+fn add_suggestion_success() {
+    let message = "unknown field `ex-info`, expected one of `source`, `source_from`, `ex_info` at line 3 column 1";
+    assert_eq!(add_suggestion(message),
+        "unknown field `ex-info`, expected one of `source`, `source_from`, `ex_info` (did you mean `ex_info`?) at line 3 column 1");
+    let message = "unknown variant `postgresql`, expected one of `mysql`, `postgres`, `sqlite`";
+    assert_eq!(add_suggestion(message).ends_with("(did you mean `postgres`?)"), true);
+    let message = "unknown field `colour`, expected `format`";
+    assert_eq!(add_suggestion(message), message);
+}
+
+#[test]
+fn detect_format_success() {
+    assert_eq!(FileFormat::from_path("config.yml"), Some(FileFormat::Yaml));
+    assert_eq!(FileFormat::from_path("data/erdh.JSON"), Some(FileFormat::Json));
+    assert_eq!(FileFormat::from_path("config"), None);
+
+    assert_eq!(FileFormat::detect("{\"source\": \"mysql\"}"), FileFormat::Json);
+    assert_eq!(FileFormat::detect("# comment\nsource = \"mysql\"\n"), FileFormat::Toml);
+    assert_eq!(FileFormat::detect("[intermediate]\nsave_to = \"a.toml\"\n"), FileFormat::Toml);
+    assert_eq!(FileFormat::detect("---\nsource: mysql\n"), FileFormat::Yaml);
+    assert_eq!(FileFormat::detect("source: mysql\n"), FileFormat::Yaml);
+}
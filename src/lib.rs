@@ -1,3 +1,6 @@
 pub mod config;
 pub mod erdh;
 pub mod db;
+pub mod file_format;
+pub mod schema;
+pub mod check;
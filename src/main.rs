@@ -3,6 +3,7 @@ extern crate serde_derive;
 extern crate serde_yaml;
 extern crate mylib;
 extern crate getopts;
+extern crate failure;
 
 use std::{env, process};
 use getopts::Options;
@@ -10,13 +11,45 @@ use std::fs::File;
 use std::io::{Write, BufWriter};
 
 #[derive(Debug)]
-struct Args {
-    config_path: String,
-    output_path: String,
+enum Args {
+    Generate {
+        config_path: String,
+        /// 省略時は設定ファイルの outputs に出力する
+        output_path: Option<String>,
+    },
+    /// 中間形式ファイルを現在の版に書き換える
+    Upgrade {
+        paths: Vec<String>,
+    },
+    /// JSON Schema を出力する
+    Schema {
+        kind: String,
+        output_path: Option<String>,
+    },
+    /// ファイルをスキーマで検証する
+    Validate {
+        kind: String,
+        paths: Vec<String>,
+    },
+    /// 設定ファイルと参照しているファイルを検証する
+    Check {
+        config_path: String,
+    },
+    /// 設定ファイルの ex_info を重ねた結果を、値ごとの指定元と共に出力する
+    ExInfo {
+        config_path: String,
+    },
+    /// 読み込んだ構造から ex_info の雛形を作る (既にあれば新しいテーブルだけを追加する)
+    Scaffold {
+        config_path: String,
+        /// 省略時は設定ファイルの ex_info (複数ある場合は最後のもの)
+        output_path: Option<String>,
+    },
 }
 
 fn print_usage(program: &str, opts: &Options) {
-    let brief = format!("Usage: {} [options]", program);
+    let brief = format!("Usage: {0} [options]\n       {0} upgrade FILE...\n       {0} schema KIND [-o FILE]\n       {0} validate KIND FILE...\n       {0} check CONFIG\n       {0} ex_info CONFIG\n       {0} scaffold CONFIG [-o FILE]\n\nKIND: {1}",
+        program, mylib::schema::SchemaKind::NAMES.join(", "));
     print!("{}", opts.usage(&brief));
     process::exit(0);
 }
@@ -40,13 +73,40 @@ fn parse_args() -> Option<Args> {
         return None;
     }
 
-    let config = matches.opt_str("c");
-    let output = matches.opt_str("o");
+    let free = &matches.free;
+    match free.first().map(|c| c.as_str()) {
+        Some("upgrade") if free.len() > 1 => return Some(Args::Upgrade {
+            paths: free[1..].to_vec(),
+        }),
+        Some("schema") if free.len() == 2 => return Some(Args::Schema {
+            kind: free[1].clone(),
+            output_path: matches.opt_str("o"),
+        }),
+        Some("validate") if free.len() > 2 => return Some(Args::Validate {
+            kind: free[1].clone(),
+            paths: free[2..].to_vec(),
+        }),
+        Some("check") if free.len() == 2 => return Some(Args::Check {
+            config_path: free[1].clone(),
+        }),
+        Some("ex_info") if free.len() == 2 => return Some(Args::ExInfo {
+            config_path: free[1].clone(),
+        }),
+        Some("scaffold") if free.len() == 2 => return Some(Args::Scaffold {
+            config_path: free[1].clone(),
+            output_path: matches.opt_str("o"),
+        }),
+        Some(_) => {
+            print_usage(&program, &opts);
+            return None;
+        }
+        None => {}
+    }
 
-    if let (Some(config_path), Some(output_path)) = (config, output) {
-        Some(Args {
+    if let Some(config_path) = matches.opt_str("c") {
+        Some(Args::Generate {
             config_path: config_path,
-            output_path: output_path,
+            output_path: matches.opt_str("o"),
         })
     } else {
         print_usage(&program, &opts);
@@ -55,31 +115,231 @@ fn parse_args() -> Option<Args> {
 }
 
 fn main() {
-    let args = parse_args().unwrap();
-    let config = mylib::config::Config::from_yaml_file(&args.config_path).unwrap();
+    match parse_args().unwrap() {
+        Args::Generate { config_path, output_path } => generate(&config_path, output_path),
+        Args::Upgrade { paths } => {
+            for path in &paths {
+                if let Err(e) = upgrade(path) {
+                    eprintln!("error: {}: {}", path, e);
+                    process::exit(1);
+                }
+            }
+        }
+        Args::Schema { kind, output_path } => {
+            if let Err(e) = write_schema(&kind, output_path) {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        }
+        Args::Validate { kind, paths } => {
+            let kind = match mylib::schema::SchemaKind::from_name(&kind) {
+                Ok(kind) => kind,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    process::exit(1);
+                }
+            };
+            let mut failed = false;
+            for path in &paths {
+                match mylib::schema::validate_file(kind, path) {
+                    Ok(_) => println!("{}: ok", path),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                process::exit(1);
+            }
+        }
+        Args::Check { config_path } => {
+            let problems = mylib::check::check_config(&config_path);
+            if problems.len() == 0 {
+                println!("{}: ok", config_path);
+            } else {
+                for problem in &problems {
+                    eprintln!("{}", problem);
+                }
+                process::exit(1);
+            }
+        }
+        Args::Scaffold { config_path, output_path } => {
+            if let Err(e) = scaffold(&config_path, output_path) {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        }
+        Args::ExInfo { config_path } => {
+            if let Err(e) = print_ex_info(&config_path) {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn scaffold(config_path: &str, output_path: Option<String>) -> Result<(), failure::Error> {
+    let config = mylib::config::Config::from_file(config_path).map_err(|e| failure::format_err!("{}: {}", config_path, e))?;
+    let path = match output_path.or(config.ex_info.as_ref().and_then(|p| p.iter().last().cloned())) {
+        Some(path) => path,
+        None => return Err(failure::format_err!("no output: specify -o or ex_info in the config")),
+    };
+    if std::path::Path::new(&path).is_dir() {
+        return Err(failure::format_err!("{} is a directory: specify the file to write with -o", path));
+    }
+    // 初めて実行するときは ex_info がまだ無い
+    let mut cons = mylib::erdh::read_construction(&config)?;
+    if let Some(patterns) = &config.table_patterns {
+        for warning in mylib::erdh::collapse_table_patterns(&mut cons, patterns)? {
+            eprintln!("warning: {}", warning);
+        }
+    }
+    let (written, added) = mylib::erdh::scaffold::write_scaffold(&cons, &path)?;
+    if added.len() == 0 {
+        println!("{} already lists every table", path);
+    } else {
+        println!("added {} tables to {}: {}", added.len(), written, added.join(", "));
+    }
+    Ok(())
+}
+
+fn print_ex_info(config_path: &str) -> Result<(), failure::Error> {
+    let config = mylib::config::Config::from_file(config_path).map_err(|e| failure::format_err!("{}: {}", config_path, e))?;
+    let layered = mylib::config::extra_config::LayeredExtraConfig::from_files(&config.get_ex_info_files()?)?;
+    let stdout = std::io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    layered.write_with_origins(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
 
-    let mut cons = mylib::erdh::get_construction(&config).unwrap();
-    if let Some(ex_info_path) = &config.ex_info {
-        let ex_info = mylib::config::extra_config::ExtraConfig::from_yaml_file(&ex_info_path);
-        if ex_info.is_ok() {
-            mylib::erdh::apply_ex_info(&mut cons, &ex_info.unwrap());
+fn write_schema(kind: &str, output_path: Option<String>) -> Result<(), failure::Error> {
+    let json = mylib::schema::schema_json(mylib::schema::SchemaKind::from_name(kind)?)?;
+    match output_path {
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(&path)?);
+            writer.write_all(json.as_bytes())?;
+            writer.flush()?;
+            println!("saving {} schema to {}", kind, path);
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+/// 元のファイルと同じ形式で書き戻す
+fn upgrade(path: &str) -> Result<(), failure::Error> {
+    let (contents, format) = mylib::file_format::read_file(path)?;
+    let value: serde_yaml::Value = format.parse(&contents)?;
+    let version = mylib::erdh::migration::read_format_version(&value)?;
+    if version == mylib::erdh::migration::FORMAT_VERSION {
+        println!("{} is already format_version {}", path, version);
+        return Ok(());
+    }
+
+    let cons = mylib::erdh::erdh_data::Construction::from_contents(&contents, format)?;
+    let s = format.to_string(&cons)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(s.as_bytes())?;
+    writer.flush()?;
+    println!("upgraded {} from format_version {} to {}", path, version, cons.format_version);
+    Ok(())
+}
+
+fn generate(config_path: &str, output_path: Option<String>) {
+    let config = match mylib::config::Config::from_file(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}: {}", config_path, e);
+            process::exit(1);
+        }
+    };
+    let outputs = match config.get_outputs(output_path.as_ref().map(|p| p.as_str())) {
+        Ok(outputs) => outputs,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut cons = match mylib::erdh::get_construction(&config) {
+        Ok(cons) => cons,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    };
+    // ex_info はまとめた後に適用する (まとめる前のテーブル名での指定も効く)
+    if let Some(patterns) = &config.table_patterns {
+        match mylib::erdh::collapse_table_patterns(&mut cons, patterns) {
+            Ok(warnings) => for warning in warnings {
+                eprintln!("warning: {}", warning);
+            },
+            Err(e) => {
+                eprintln!("error: table_patterns: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    let ex_info_files = match config.get_ex_info_files() {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    };
+    if ex_info_files.len() > 0 {
+        let strict = config.ex_info_strict.unwrap_or(false);
+        let mut layered = mylib::config::extra_config::LayeredExtraConfig::new();
+        let mut warning_count = 0;
+        for ex_info_path in &ex_info_files {
+            let ex_info = match mylib::config::extra_config::ExtraConfig::from_file(ex_info_path) {
+                Ok(ex_info) => ex_info,
+                Err(e) => {
+                    eprintln!("error: ex_info ({}): {}", ex_info_path, e);
+                    process::exit(1);
+                }
+            };
+            // 存在しないテーブルなどはファイルごとに確かめる
+            for warning in mylib::erdh::ex_info_check::validate_ex_info(&cons, &ex_info) {
+                if strict {
+                    eprintln!("error: ex_info ({}): {}", ex_info_path, warning);
+                } else {
+                    eprintln!("warning: ex_info ({}): {}", ex_info_path, warning);
+                }
+                warning_count += 1;
+            }
+            layered.push(ex_info_path, ex_info);
+        }
+        if strict && warning_count > 0 {
+            process::exit(1);
+        }
+        if let Err(e) = mylib::erdh::apply_ex_info(&mut cons, &layered.ex_info) {
+            eprintln!("error: ex_info: {}", e);
+            process::exit(1);
         }
     }
 
     // 中間形式ファイルを保存
-    if let Some(im) = config.intermediate {
-        if let Some(save_to) = im.save_to {
+    if let Some(im) = &config.intermediate {
+        let format = im.get_format();
+        if let Some(save_to) = &im.save_to {
             println!("saving intermediate data to {}", &save_to);
-            let s = serde_yaml::to_string(&cons).unwrap();
+            let s = format.to_string(&cons).unwrap();
             let mut writer_y = BufWriter::new(File::create(save_to).unwrap());
             writer_y.write_all(s.as_bytes()).unwrap();
             writer_y.flush().unwrap();
         }
     }
 
-    // pumlを保存
-    println!("saving plantuml data to {}", &args.output_path);
-    let mut writer = BufWriter::new(File::create(args.output_path).unwrap());
-    mylib::erdh::plantuml::write_puml(&cons, &mut writer, config.group).unwrap();
-    writer.flush().unwrap();
+    // 図を保存
+    for output in &outputs {
+        for (path, groups) in mylib::erdh::expand_output(&cons, output) {
+            println!("saving {} data to {}", format!("{:?}", output.get_format()).to_lowercase(), &path);
+            let mut writer = BufWriter::new(File::create(&path).unwrap());
+            mylib::erdh::write_output(&cons, output, groups, &mut writer).unwrap();
+            writer.flush().unwrap();
+        }
+    }
 }
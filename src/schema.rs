@@ -0,0 +1,142 @@
+//! 設定ファイル・ex_info・中間形式ファイルの JSON Schema と検証
+//!
+//! スキーマは読み込みに使う型から生成し、検証も同じ型での読み込みで行うので両者はずれない
+use super::config::Config;
+use super::config::db_config::DbConfig;
+use super::config::extra_config::ExtraConfig;
+use super::erdh::erdh_data::Construction;
+use super::erdh::migration;
+use super::file_format::{self, FileFormat};
+use regex::Regex;
+use schemars::schema_for;
+use serde::de::DeserializeOwned;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
+extern crate failure;
+
+/// スキーマを持つファイルの種類
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum SchemaKind {
+    Config,
+    DbConfig,
+    ExInfo,
+    Intermediate,
+}
+
+impl SchemaKind {
+    pub const NAMES: [&'static str; 4] = ["config", "db_config", "ex_info", "intermediate"];
+
+    pub fn from_name(name: &str) -> Result<SchemaKind, failure::Error> {
+        match name {
+            "config" => Ok(SchemaKind::Config),
+            "db_config" => Ok(SchemaKind::DbConfig),
+            "ex_info" => Ok(SchemaKind::ExInfo),
+            "intermediate" => Ok(SchemaKind::Intermediate),
+            _ => Err(failure::format_err!("unknown schema: {} (expected one of {})", name, Self::NAMES.join(", "))),
+        }
+    }
+}
+
+/// JSON Schema を JSON 文字列で返す
+pub fn schema_json(kind: SchemaKind) -> Result<String, failure::Error> {
+    let schema = match kind {
+        SchemaKind::Config => schema_for!(Config),
+        SchemaKind::DbConfig => schema_for!(DbConfig),
+        SchemaKind::ExInfo => schema_for!(ExtraConfig),
+        SchemaKind::Intermediate => schema_for!(Construction),
+    };
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
+/// ファイルを検証する。エラーは "パス:行:列: 内容" の形式
+pub fn validate_file(kind: SchemaKind, path: &str) -> Result<(), failure::Error> {
+    let (contents, format) = file_format::read_file(path)?;
+    validate(kind, &contents, format).map_err(|e| {
+        let located = Regex::new(r"^\d+:\d+: ").unwrap();
+        let message = e.to_string();
+        if located.is_match(&message) {
+            failure::format_err!("{}:{}", path, message)
+        } else {
+            failure::format_err!("{}: {}", path, message)
+        }
+    })
+}
+
+pub fn validate(kind: SchemaKind, contents: &str, format: FileFormat) -> Result<(), failure::Error> {
+    match kind {
+        SchemaKind::Config => {
+            let config: Config = parse_located(contents, format)?;
+            config.get_sources()?;
+        }
+        SchemaKind::DbConfig => {
+            let _: DbConfig = parse_located(contents, format)?;
+            // 環境変数の展開と URL の解釈も確かめる
+            DbConfig::from_contents(contents, format)?;
+        }
+        SchemaKind::ExInfo => {
+            let _: ExtraConfig = parse_located(contents, format)?;
+        }
+        SchemaKind::Intermediate => {
+            let value: serde_yaml::Value = parse_located(contents, format)?;
+            if migration::read_format_version(&value)? == migration::FORMAT_VERSION {
+                let _: Construction = parse_located(contents, format)?;
+            } else {
+                // 古い版は変換後に検証するので位置は分からない
+                Construction::from_contents(contents, format)
+                    .map_err(|e| failure::format_err!("{} (after upgrading from an older format_version)", e))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 読み込みエラーを "行:列: 内容" の形式にする
+fn parse_located<T: DeserializeOwned>(contents: &str, format: FileFormat) -> Result<T, failure::Error> {
+    let (location, message) = match format {
+        FileFormat::Yaml => match serde_yaml::from_str(contents) {
+            Ok(value) => return Ok(value),
+            Err(e) => (e.location().map(|l| (l.line(), l.column())), e.to_string()),
+        },
+        FileFormat::Json => match serde_json::from_str(contents) {
+            Ok(value) => return Ok(value),
+            Err(e) => (Some((e.line(), e.column())), e.to_string()),
+        },
+        FileFormat::Toml => match toml::from_str(contents) {
+            Ok(value) => return Ok(value),
+            // toml の行と列は 0 始まり
+            Err(e) => (e.line_col().map(|(l, c)| (l + 1, c + 1)), e.to_string()),
+        },
+    };
+    let re = Regex::new(r" at line \d+ column \d+$").unwrap();
+    let message = file_format::add_suggestion(&re.replace(&message, ""));
+    match location {
+        Some((line, column)) => Err(failure::format_err!("{}:{}: {}", line, column, message)),
+        None => Err(failure::format_err!("{}", message)),
+    }
+}
+
+#[test]
+fn schema_json_success() {
+    for name in SchemaKind::NAMES.iter() {
+        let json = schema_json(SchemaKind::from_name(name).unwrap()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["type"], "object");
+    }
+    assert_eq!(SchemaKind::from_name("unknown").is_err(), true);
+}
+
+#[test]
+fn validate_success() {
+    let yaml = "source: mysql\nsource_from: db.yaml\n";
+    assert_eq!(validate(SchemaKind::Config, yaml, FileFormat::Yaml).is_ok(), true);
+
+    let yaml = "source: mysql\nsource_from: db.yaml\nintermediate:\n  format: xml\n";
+    let message = validate(SchemaKind::Config, yaml, FileFormat::Yaml).unwrap_err().to_string();
+    assert_eq!(message.starts_with("4:"), true, "{}", message);
+
+    let json = "{\n  \"dbtype\": \"mysql\",\n  \"port\": 3306\n}";
+    let message = validate(SchemaKind::DbConfig, json, FileFormat::Json).unwrap_err().to_string();
+    assert_eq!(message.starts_with("3:"), true, "{}", message);
+}